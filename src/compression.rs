@@ -0,0 +1,113 @@
+//! Message compression, enabled with the `lz4` feature
+
+use async_tungstenite::tungstenite::Message;
+
+use crate::Extension;
+
+/// The largest message [`Lz4Compression`] decompresses by default, matching tungstenite's
+/// default maximum message size
+pub const DEFAULT_MAX_DECOMPRESSED_SIZE: usize = 64 << 20;
+
+/// Compresses `data` with LZ4, prefixing it with its uncompressed size
+pub fn lz4_compress(data: &[u8]) -> Vec<u8> {
+    lz4_flex::compress_prepend_size(data)
+}
+
+/// Decompresses data produced by [`lz4_compress`]
+///
+/// Fails without allocating if the uncompressed size is larger than `max_size`.
+pub fn lz4_decompress(data: &[u8], max_size: usize) -> Result<Vec<u8>, String> {
+    let size = data
+        .get(..4)
+        .map(|size| u32::from_le_bytes(size.try_into().expect("Slice is 4 bytes long")))
+        .ok_or_else(|| "Compressed message is missing its size".to_string())?;
+
+    if size as usize > max_size {
+        return Err(format!(
+            "Decompressed message would be {} bytes, more than the maximum of {}",
+            size, max_size
+        ));
+    }
+
+    lz4_flex::decompress_size_prepended(data).map_err(|err| err.to_string())
+}
+
+/// An [`Extension`] compressing every binary message with LZ4
+///
+/// LZ4 trades compression ratio for speed, which suits large, frequent game state snapshots.
+/// Both ends of the connection must register it, or the handshake fails.
+#[derive(Clone, Copy, Debug)]
+pub struct Lz4Compression {
+    /// The largest message accepted after decompression, in bytes
+    pub max_decompressed_size: usize,
+}
+
+impl Default for Lz4Compression {
+    fn default() -> Self {
+        Self {
+            max_decompressed_size: DEFAULT_MAX_DECOMPRESSED_SIZE,
+        }
+    }
+}
+
+impl Extension for Lz4Compression {
+    fn name(&self) -> &str {
+        "lz4"
+    }
+
+    fn on_send(&self, message: Message) -> Result<Message, String> {
+        match message {
+            Message::Binary(binary) => Ok(Message::Binary(lz4_compress(&binary))),
+            message => Ok(message),
+        }
+    }
+
+    fn on_recv(&self, message: Message) -> Result<Message, String> {
+        match message {
+            Message::Binary(binary) => Ok(Message::Binary(lz4_decompress(
+                &binary,
+                self.max_decompressed_size,
+            )?)),
+            message => Ok(message),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trips_binary_messages() {
+        let compression = Lz4Compression::default();
+        let data = vec![7; 1000];
+        let compressed = compression.on_send(Message::Binary(data.clone())).unwrap();
+        assert!(compressed.len() < data.len());
+        assert_eq!(
+            compression.on_recv(compressed).unwrap(),
+            Message::Binary(data)
+        );
+    }
+
+    #[test]
+    fn passes_control_messages_through() {
+        let compression = Lz4Compression::default();
+        let ping = Message::Ping(vec![1, 2, 3]);
+        assert_eq!(compression.on_send(ping.clone()).unwrap(), ping);
+        assert_eq!(compression.on_recv(ping.clone()).unwrap(), ping);
+    }
+
+    #[test]
+    fn rejects_oversized_messages() {
+        let compression = Lz4Compression {
+            max_decompressed_size: 100,
+        };
+        let compressed = lz4_compress(&[0; 101]);
+        assert!(compression.on_recv(Message::Binary(compressed)).is_err());
+    }
+
+    #[test]
+    fn rejects_missing_size() {
+        assert!(lz4_decompress(&[1, 2], DEFAULT_MAX_DECOMPRESSED_SIZE).is_err());
+    }
+}
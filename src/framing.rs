@@ -0,0 +1,115 @@
+//! A self-describing wire format for [`NetworkPacket`]s
+//!
+//! WebSocket frames already carry their own length, but byte streams such as raw TCP do not.
+//! These helpers prefix the bincode encoded packet with its length as a 4 byte big-endian
+//! integer so the same packets can be sent over either kind of transport.
+
+use bevy_eventwork::NetworkPacket;
+
+/// The number of bytes used by the length prefix
+pub const LENGTH_PREFIX_SIZE: usize = 4;
+
+/// Encodes a packet as a 4 byte big-endian length followed by the bincode payload
+///
+/// Fails if the packet cannot be encoded or its encoding does not fit in the length prefix.
+pub fn encode_with_length_prefix(packet: NetworkPacket) -> Result<Vec<u8>, String> {
+    let payload = bincode::serialize(&packet)
+        .map_err(|err| format!("Failed to encode network packet: {}", err))?;
+    let length = u32::try_from(payload.len()).map_err(|_| {
+        format!(
+            "Network packet of {} bytes does not fit in the length prefix",
+            payload.len()
+        )
+    })?;
+
+    let mut bytes = Vec::with_capacity(LENGTH_PREFIX_SIZE + payload.len());
+    bytes.extend_from_slice(&length.to_be_bytes());
+    bytes.extend_from_slice(&payload);
+    Ok(bytes)
+}
+
+/// Decodes the first length prefixed packet in `bytes`
+///
+/// Returns the packet and the number of bytes it took up, so any remaining bytes can be
+/// decoded by calling this again on the rest of the buffer.
+pub fn decode_with_length_prefix(bytes: &[u8]) -> Result<(NetworkPacket, usize), String> {
+    let Some(prefix) = bytes.get(..LENGTH_PREFIX_SIZE) else {
+        return Err(format!(
+            "Expected a {} byte length prefix but only {} bytes are available",
+            LENGTH_PREFIX_SIZE,
+            bytes.len()
+        ));
+    };
+
+    let length = u32::from_be_bytes(prefix.try_into().expect("Prefix is 4 bytes long")) as usize;
+    // A hostile prefix can overflow the end on 32-bit targets such as wasm32
+    let end = LENGTH_PREFIX_SIZE.checked_add(length);
+
+    let Some(payload) = end.and_then(|end| bytes.get(LENGTH_PREFIX_SIZE..end)) else {
+        return Err(format!(
+            "Expected a {} byte packet but only {} bytes are available",
+            length,
+            bytes.len() - LENGTH_PREFIX_SIZE
+        ));
+    };
+
+    let packet = bincode::deserialize(payload)
+        .map_err(|err| format!("Failed to decode network packet: {}", err))?;
+
+    Ok((packet, LENGTH_PREFIX_SIZE + payload.len()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `NetworkPacket`'s fields are private, so packets are built from their encoding
+    fn packet(kind: &str, data: &[u8]) -> NetworkPacket {
+        let encoded = bincode::serialize(&(kind, data)).unwrap();
+        bincode::deserialize(&encoded).unwrap()
+    }
+
+    #[test]
+    fn round_trips_consecutive_packets() {
+        let mut bytes = encode_with_length_prefix(packet("first", &[1, 2, 3])).unwrap();
+        bytes.extend(encode_with_length_prefix(packet("second", &[])).unwrap());
+
+        let (first, used) = decode_with_length_prefix(&bytes).unwrap();
+        assert_eq!(
+            format!("{:?}", first),
+            format!("{:?}", packet("first", &[1, 2, 3]))
+        );
+
+        let (second, rest) = decode_with_length_prefix(&bytes[used..]).unwrap();
+        assert_eq!(
+            format!("{:?}", second),
+            format!("{:?}", packet("second", &[]))
+        );
+        assert_eq!(used + rest, bytes.len());
+    }
+
+    #[test]
+    fn rejects_truncated_prefix() {
+        assert!(decode_with_length_prefix(&[]).is_err());
+        assert!(decode_with_length_prefix(&[0, 0, 1]).is_err());
+    }
+
+    #[test]
+    fn rejects_truncated_payload() {
+        let bytes = encode_with_length_prefix(packet("kind", &[1, 2, 3])).unwrap();
+        assert!(decode_with_length_prefix(&bytes[..bytes.len() - 1]).is_err());
+    }
+
+    #[test]
+    fn rejects_oversized_prefix() {
+        let mut bytes = u32::MAX.to_be_bytes().to_vec();
+        bytes.extend_from_slice(&[0; 16]);
+        assert!(decode_with_length_prefix(&bytes).is_err());
+    }
+
+    #[test]
+    fn rejects_invalid_payload() {
+        let bytes = [0, 0, 0, 2, 0xff, 0xff];
+        assert!(decode_with_length_prefix(&bytes).is_err());
+    }
+}
@@ -7,271 +7,2228 @@ pub type WebSocketProvider = native_websocket::NativeWesocketProvider;
 pub type WebSocketProvider = wasm_websocket::WasmWebSocketProvider;
 
 #[cfg(not(target_arch = "wasm32"))]
-pub use native_websocket::NetworkSettings;
+pub use native_websocket::{
+    BalancingPolicy, BindMode, Extension, ExtensionRegistry, FlushStrategy, NetworkLoadBalancer,
+    NetworkSettings, NetworkSettingsBuilder, ProxyConfig,
+};
+
+#[cfg(all(feature = "handshake", not(target_arch = "wasm32")))]
+pub use native_websocket::HandshakeFn;
+#[cfg(not(target_arch = "wasm32"))]
+pub use native_websocket::HandshakeTimeout;
+#[cfg(all(not(target_arch = "wasm32"), debug_assertions))]
+pub use native_websocket::MockNetworkConditions;
+#[cfg(not(target_arch = "wasm32"))]
+pub use plugin::{BoundAddress, UpgradeResponse};
+#[cfg(not(target_arch = "wasm32"))]
+pub use shutdown::ShutdownTimeout;
+#[cfg(not(target_arch = "wasm32"))]
+pub use transport::MaybeTlsStream;
 
 #[cfg(target_arch = "wasm32")]
 pub use wasm_websocket::NetworkSettings;
 
+#[cfg(all(feature = "lz4", not(target_arch = "wasm32")))]
+pub mod compression;
+pub mod framing;
+#[cfg(feature = "perf-metrics")]
+pub mod metrics;
+mod packet;
+mod peers;
+mod plugin;
+#[cfg(not(target_arch = "wasm32"))]
+mod shutdown;
+#[cfg(feature = "hmac")]
+mod signing;
+#[cfg(feature = "states")]
+pub mod states;
+mod throttle;
+#[cfg(all(feature = "tls", not(target_arch = "wasm32")))]
+pub mod tls;
+#[cfg(not(target_arch = "wasm32"))]
+mod transport;
+
+#[cfg(not(target_arch = "wasm32"))]
+pub use plugin::WebSocketServerPlugin;
+pub use plugin::{
+    ConnectedPeerCount, NetworkPlugin, WebSocketClientPlugin, WebSocketPlugin,
+    WebSocketPluginGroup, WebSocketSet,
+};
+
 #[cfg(not(target_arch = "wasm32"))]
 mod native_websocket {
-    use std::{net::SocketAddr, pin::Pin};
+    use std::{
+        net::{Shutdown, SocketAddr},
+        pin::Pin,
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc, Mutex,
+        },
+        time::Duration,
+    };
 
     use async_channel::{Receiver, Sender};
     use async_std::net::{TcpListener, TcpStream};
     use async_trait::async_trait;
     use async_tungstenite::{
-        tungstenite::{protocol::WebSocketConfig, Message},
+        tungstenite::{
+            client::IntoClientRequest,
+            handshake::server::{Callback, ErrorResponse, Request, Response},
+            http::{
+                header::{SEC_WEBSOCKET_EXTENSIONS, SEC_WEBSOCKET_PROTOCOL},
+                HeaderMap, HeaderName, HeaderValue, StatusCode,
+            },
+            protocol::WebSocketConfig,
+            Message,
+        },
         WebSocketStream,
     };
-    use bevy::prelude::{error, info, trace, Deref, DerefMut, Resource};
+    use base64::{prelude::BASE64_STANDARD, Engine};
+    use bevy::{
+        ecs::world::WorldId,
+        prelude::{error, info, trace, warn, Deref, DerefMut, Resource},
+    };
     use bevy_eventwork::{error::NetworkError, managers::NetworkProvider, NetworkPacket};
     use futures::{
-        stream::{SplitSink, SplitStream},
-        SinkExt, StreamExt,
+        stream::{FuturesUnordered, SplitSink, SplitStream},
+        AsyncReadExt, AsyncWriteExt, SinkExt, StreamExt,
     };
     use futures_lite::{Future, FutureExt, Stream};
+    use socket2::{Domain, Protocol, Socket, Type};
+
+    use crate::{
+        packet::{packet_kind, KindFilter},
+        peers::PeerCounter,
+        plugin::UpgradeResponse,
+        shutdown::{ShutdownSignal, ShutdownTimeout},
+        throttle::{ErrorRateLimit, LogThrottle},
+    };
+
+    /// The byte stream WebSocket connections run over
+    type Transport = crate::transport::MaybeTlsStream;
+
+    /// A provider for WebSockets
+    #[derive(Default, Debug)]
+    pub struct NativeWesocketProvider;
+
+    #[cfg_attr(not(target_arch = "wasm32"), async_trait)]
+    #[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
+    impl NetworkProvider for NativeWesocketProvider {
+        type NetworkSettings = NetworkSettings;
+
+        type Socket = WebSocketStream<Transport>;
+
+        type ReadHalf = WebSocketReadHalf;
+
+        type WriteHalf = WebSocketWriteHalf;
+
+        type ConnectInfo = url::Url;
+
+        type AcceptInfo = SocketAddr;
+
+        type AcceptStream = OwnedIncoming;
+
+        async fn accept_loop(
+            accept_info: Self::AcceptInfo,
+            network_settings: Self::NetworkSettings,
+        ) -> Result<Self::AcceptStream, NetworkError> {
+            network_settings
+                .validate()
+                .map_err(|err| NetworkError::Error(format!("Configuration Error: {}", err)))?;
+            let listeners = network_settings
+                .bind_mode
+                .bind(accept_info, network_settings.reuse_port)
+                .map_err(NetworkError::Listen)?;
+            let bound_addr = listeners[0].local_addr().map_err(NetworkError::Listen)?;
+            *network_settings
+                .bound_addr
+                .lock()
+                .expect("Bound address was poisoned") = Some(bound_addr);
+            Ok(OwnedIncoming::new(listeners, &network_settings))
+        }
+
+        async fn connect_task(
+            connect_info: Self::ConnectInfo,
+            network_settings: Self::NetworkSettings,
+        ) -> Result<Self::Socket, NetworkError> {
+            info!("Beginning connection");
+            network_settings
+                .validate()
+                .map_err(|err| NetworkError::Error(format!("Configuration Error: {}", err)))?;
+            if connect_info.scheme() == "wss" {
+                // Sending the handshake in clear text to a TLS server would only fail later
+                return Err(NetworkError::Error(String::from(
+                    "Url Error: native clients do not support wss://",
+                )));
+            }
+            let host = connect_info
+                .host()
+                .ok_or_else(|| NetworkError::Error(String::from("Url Error: missing host")))?;
+            let port = connect_info
+                .port_or_known_default()
+                .ok_or_else(|| NetworkError::Error(String::from("Url Error: missing port")))?;
+            let tcp_stream = match &network_settings.proxy {
+                // The proxy expects IPv6 hosts in brackets, as `host_str` returns them
+                Some(proxy) => proxy.tunnel(&host.to_string(), port).await?,
+                None => match host {
+                    url::Host::Domain(domain) => TcpStream::connect((domain, port)).await,
+                    url::Host::Ipv4(ip) => TcpStream::connect((ip, port)).await,
+                    url::Host::Ipv6(ip) => TcpStream::connect((ip, port)).await,
+                }
+                .map_err(|err| NetworkError::Error(format!("Io Error: {}", err)))?,
+            };
+            let tcp_stream = Transport::Plain(tcp_stream);
+
+            let mut request = connect_info
+                .into_client_request()
+                .map_err(handshake_error)?;
+            for (name, value) in &network_settings.headers {
+                let name = HeaderName::try_from(name.as_str())
+                    .map_err(|err| NetworkError::Error(format!("Header Error: {}", err)))?;
+                let value = HeaderValue::try_from(value.as_str())
+                    .map_err(|err| NetworkError::Error(format!("Header Error: {}", err)))?;
+                request.headers_mut().append(name, value);
+            }
+            if !network_settings.subprotocols.is_empty() {
+                let subprotocols = HeaderValue::try_from(network_settings.subprotocols.join(", "))
+                    .map_err(|err| NetworkError::Error(format!("Header Error: {}", err)))?;
+                request
+                    .headers_mut()
+                    .insert(SEC_WEBSOCKET_PROTOCOL, subprotocols);
+            }
+            let extensions = network_settings.extensions.names();
+            if !extensions.is_empty() {
+                let extensions = HeaderValue::try_from(extensions.join(", "))
+                    .map_err(|err| NetworkError::Error(format!("Header Error: {}", err)))?;
+                request
+                    .headers_mut()
+                    .insert(SEC_WEBSOCKET_EXTENSIONS, extensions);
+            }
+
+            let (stream, response) = async_tungstenite::client_async_with_config(
+                request,
+                tcp_stream,
+                Some(*network_settings),
+            )
+            .await
+            .map_err(handshake_error)?;
+            // tungstenite does not check the extensions the server accepted
+            if offered_extensions(response.headers()) != extensions {
+                return Err(NetworkError::Error(String::from(
+                    "Handshake Error: the server did not accept the registered extensions",
+                )));
+            }
+            *network_settings
+                .upgrade_response
+                .lock()
+                .expect("Upgrade response was poisoned") = Some(UpgradeResponse {
+                status: response.status().as_u16(),
+                headers: response
+                    .headers()
+                    .iter()
+                    .map(|(name, value)| {
+                        (
+                            name.as_str().to_owned(),
+                            String::from_utf8_lossy(value.as_bytes()).into_owned(),
+                        )
+                    })
+                    .collect(),
+            });
+            info!("Connected!");
+            return Ok(stream);
+        }
+
+        async fn recv_loop(
+            mut read_half: Self::ReadHalf,
+            messages: Sender<NetworkPacket>,
+            settings: Self::NetworkSettings,
+        ) {
+            let _peer = settings.peers.track();
+            let mut log_throttle = LogThrottle::new(read_half.peer.clone());
+            let mut error_rate_limit = settings
+                .error_rate_limit
+                .map(|(limit, window)| ErrorRateLimit::new(limit, window));
+
+            loop {
+                let next = if let Some(inactivity_timeout) = settings.inactivity_timeout {
+                    match read_half.next_within(inactivity_timeout).await {
+                        Some(next) => next,
+                        None => {
+                            trace!("Connection inactive, sending ping");
+                            let _ = read_half.control.try_send(Message::Ping(Vec::new()));
+                            match read_half.next_within(INACTIVITY_GRACE_PERIOD).await {
+                                Some(next) => next,
+                                None => {
+                                    warn!(
+                                        "Connection inactive for {:?}, closing connection",
+                                        inactivity_timeout + INACTIVITY_GRACE_PERIOD
+                                    );
+                                    break;
+                                }
+                            }
+                        }
+                    }
+                } else {
+                    match settings.recv_timeout {
+                        Some(recv_timeout) => match read_half.next_within(recv_timeout).await {
+                            Some(next) => next,
+                            None if settings.disconnect_on_recv_timeout => {
+                                warn!(
+                                    "No message received within {:?}, closing connection",
+                                    recv_timeout
+                                );
+                                break;
+                            }
+                            None => continue,
+                        },
+                        None => read_half.stream.next().await,
+                    }
+                };
+
+                let message = match next {
+                    Some(message) => match message {
+                        Ok(message) => message,
+                        Err(err) => match err {
+                            async_tungstenite::tungstenite::Error::ConnectionClosed
+                            | async_tungstenite::tungstenite::Error::AlreadyClosed => {
+                                error!("Connection Closed");
+                                break;
+                            }
+                            _ => {
+                                if error_rate_limit
+                                    .as_mut()
+                                    .is_some_and(ErrorRateLimit::exceeded)
+                                {
+                                    error!("Too many errors from peer, closing connection");
+                                    break;
+                                }
+                                if log_throttle.allow() {
+                                    error!("Nonfatal error detected: {}", err);
+                                }
+                                continue;
+                            }
+                        },
+                    },
+                    None => {
+                        // The stream only ends once the connection has been torn down
+                        trace!("Stream exhausted, closing recv loop");
+                        break;
+                    }
+                };
+
+                let message = match settings.extensions.on_recv(message) {
+                    Ok(message) => message,
+                    Err(err) => {
+                        error!("Extension rejected message: {}", err);
+                        break;
+                    }
+                };
+
+                let binary = match message {
+                    Message::Text(_) => {
+                        error!("Text Message Received");
+                        break;
+                    }
+                    Message::Binary(binary) => binary,
+                    Message::Ping(_) => {
+                        // tungstenite queues the matching pong itself and sends it on the next
+                        // read or write
+                        trace!("Ping Message Received");
+                        continue;
+                    }
+                    Message::Pong(_) => {
+                        trace!("Pong Message Received");
+                        continue;
+                    }
+                    Message::Close(_) => {
+                        error!("Connection Closed");
+                        break;
+                    }
+                    Message::Frame(_) => {
+                        // tungstenite reassembles fragmented messages itself and never returns
+                        // raw frames from a read
+                        warn!("Unexpected raw frame received, ignoring it");
+                        continue;
+                    }
+                };
+
+                #[cfg(feature = "hmac")]
+                let mut binary = binary;
+                #[cfg(feature = "hmac")]
+                if let Some(key) = &settings.hmac_key {
+                    if !crate::signing::verify(key, &mut binary) {
+                        error!("Packet failed HMAC verification, closing connection");
+                        break;
+                    }
+                }
+
+                if !settings.allowed_kinds.allows(&binary) {
+                    if error_rate_limit
+                        .as_mut()
+                        .is_some_and(ErrorRateLimit::exceeded)
+                    {
+                        error!("Too many errors from peer, closing connection");
+                        break;
+                    }
+                    if log_throttle.allow() {
+                        warn!(
+                            "Dropping packet with unregistered kind {:?}",
+                            packet_kind(&binary)
+                        );
+                    }
+                    continue;
+                }
+
+                let packet = match bincode::deserialize(&binary) {
+                    Ok(packet) => packet,
+                    Err(err) => {
+                        error!("Failed to decode network packet from: {}", err);
+                        break;
+                    }
+                };
+
+                #[cfg(feature = "perf-metrics")]
+                settings.size_histogram.record_received(&binary);
+
+                if messages.send(packet).await.is_err() {
+                    error!("Failed to send decoded message to eventwork");
+                    break;
+                }
+                info!("Message deserialized and sent to eventwork");
+            }
+        }
+
+        async fn send_loop(
+            mut write_half: Self::WriteHalf,
+            messages: Receiver<NetworkPacket>,
+            settings: Self::NetworkSettings,
+        ) {
+            let _sender = settings.shutdown.track_sender();
+
+            #[cfg(debug_assertions)]
+            let messages = match settings.mock_network {
+                Some(conditions) => simulate_network(messages, conditions),
+                None => messages,
+            };
+
+            loop {
+                let outgoing = async { messages.recv().await.ok().map(Outgoing::Packet) }
+                    .or(async {
+                        match write_half.control.recv().await {
+                            Ok(message) => Some(Outgoing::Control(message)),
+                            // The recv loop has ended, the connection is closing anyway
+                            Err(_) => futures::future::pending().await,
+                        }
+                    })
+                    .or(async {
+                        settings.shutdown.triggered().await;
+                        None
+                    })
+                    .await;
+
+                let Some(outgoing) = outgoing else {
+                    break;
+                };
+
+                let sent = match outgoing {
+                    Outgoing::Packet(message) => {
+                        write_batch(&mut write_half.sink, message, &messages, &settings).await
+                    }
+                    Outgoing::Control(message) => write_half.sink.send(message).await.is_ok(),
+                };
+                if !sent {
+                    return;
+                }
+            }
+
+            if settings.shutdown.is_triggered() {
+                trace!("Shutting down, draining queued messages");
+                while let Ok(message) = messages.try_recv() {
+                    if !write_packet(&mut write_half.sink, message, &settings).await {
+                        return;
+                    }
+                }
+
+                // Closing the sink sends a `Close` frame and flushes it
+                if let Err(err) = write_half.sink.close().await {
+                    error!("Could not close connection: {}", err);
+                }
+            }
+        }
+
+        fn split(combined: Self::Socket) -> (Self::ReadHalf, Self::WriteHalf) {
+            let peer = match combined.get_ref().peer_addr() {
+                Ok(addr) => addr.to_string(),
+                Err(_) => String::from("<addr unavailable>"),
+            };
+            let (sink, stream) = combined.split();
+            let (control_sender, control_receiver) = async_channel::bounded(1);
+            (
+                WebSocketReadHalf {
+                    stream,
+                    control: control_sender,
+                    peer,
+                },
+                WebSocketWriteHalf {
+                    sink,
+                    control: control_receiver,
+                },
+            )
+        }
+    }
+
+    /// How long an inactive connection has to answer a ping before it is closed
+    const INACTIVITY_GRACE_PERIOD: Duration = Duration::from_secs(1);
+
+    /// The receiving half of a native connection
+    pub struct WebSocketReadHalf {
+        stream: SplitStream<WebSocketStream<Transport>>,
+        /// Control messages for the send loop to write, such as pings
+        control: Sender<Message>,
+        /// The address of the peer, for logging
+        peer: String,
+    }
+
+    impl WebSocketReadHalf {
+        /// Waits at most `timeout` for the next message, returning `None` if it elapses
+        async fn next_within(
+            &mut self,
+            timeout: Duration,
+        ) -> Option<Option<Result<Message, async_tungstenite::tungstenite::Error>>> {
+            async_std::future::timeout(timeout, self.stream.next())
+                .await
+                .ok()
+        }
+    }
+
+    /// The sending half of a native connection
+    pub struct WebSocketWriteHalf {
+        sink: SplitSink<WebSocketStream<Transport>, Message>,
+        control: Receiver<Message>,
+    }
+
+    /// Something for the send loop to write
+    enum Outgoing {
+        Packet(NetworkPacket),
+        Control(Message),
+    }
+
+    /// Converts an error from the client handshake into the error reported to eventwork
+    fn handshake_error(error: async_tungstenite::tungstenite::Error) -> NetworkError {
+        match error {
+            async_tungstenite::tungstenite::Error::ConnectionClosed => {
+                NetworkError::Error(String::from("Connection closed"))
+            }
+            async_tungstenite::tungstenite::Error::AlreadyClosed => {
+                NetworkError::Error(String::from("Connection was already closed"))
+            }
+            async_tungstenite::tungstenite::Error::Io(io_error) => {
+                NetworkError::Error(format!("Io Error: {}", io_error))
+            }
+            async_tungstenite::tungstenite::Error::Tls(tls_error) => {
+                NetworkError::Error(format!("Tls Error: {}", tls_error))
+            }
+            async_tungstenite::tungstenite::Error::Capacity(cap) => {
+                NetworkError::Error(format!("Capacity Error: {}", cap))
+            }
+            async_tungstenite::tungstenite::Error::Protocol(proto) => {
+                NetworkError::Error(format!("Protocol Error: {}", proto))
+            }
+            async_tungstenite::tungstenite::Error::WriteBufferFull(buf) => {
+                NetworkError::Error(format!("Write Buffer Full Error: {}", buf))
+            }
+            async_tungstenite::tungstenite::Error::Utf8 => {
+                NetworkError::Error("Utf8 Error".to_string())
+            }
+            async_tungstenite::tungstenite::Error::AttackAttempt => {
+                NetworkError::Error("Attack Attempt".to_string())
+            }
+            async_tungstenite::tungstenite::Error::Url(url) => {
+                NetworkError::Error(format!("Url Error: {}", url))
+            }
+            async_tungstenite::tungstenite::Error::Http(http) => {
+                NetworkError::Error(format!("HTTP Error: {:?}", http))
+            }
+            async_tungstenite::tungstenite::Error::HttpFormat(http_format) => {
+                NetworkError::Error(format!("HTTP Format Error: {}", http_format))
+            }
+        }
+    }
+
+    /// A hook applied to every message sent or received by the native provider
+    ///
+    /// Clients list their extensions in the `Sec-WebSocket-Extensions` handshake header and
+    /// servers only accept clients listing exactly the server's extensions in the same order, so
+    /// a peer that would misread the transformed messages, such as a browser, is rejected during
+    /// the handshake. Control messages such as `Close` are passed through too and should usually
+    /// be returned unchanged.
+    pub trait Extension: Send + Sync + 'static {
+        /// The name sent in the `Sec-WebSocket-Extensions` header and used when logging the
+        /// extension
+        ///
+        /// This must be a valid HTTP token, so it cannot contain spaces, commas or semicolons.
+        fn name(&self) -> &str;
+
+        /// Transforms a message before it is written to the socket
+        fn on_send(&self, message: Message) -> Result<Message, String>;
+
+        /// Transforms a message after it is read from the socket, undoing [`Extension::on_send`]
+        fn on_recv(&self, message: Message) -> Result<Message, String>;
+    }
+
+    /// The [`Extension`]s registered on a [`NetworkSettings`]
+    #[derive(Clone, Default)]
+    pub struct ExtensionRegistry(Arc<Vec<Arc<dyn Extension>>>);
+
+    impl ExtensionRegistry {
+        /// Adds an extension. Outgoing messages pass through extensions in registration order
+        /// and incoming messages in reverse order
+        pub fn register(&mut self, extension: impl Extension) {
+            Arc::make_mut(&mut self.0).push(Arc::new(extension));
+        }
+
+        /// The names of the registered extensions, in registration order
+        fn names(&self) -> Vec<&str> {
+            self.0.iter().map(|extension| extension.name()).collect()
+        }
+
+        fn on_send(&self, message: Message) -> Result<Message, String> {
+            self.0.iter().try_fold(message, |message, extension| {
+                extension
+                    .on_send(message)
+                    .map_err(|err| format!("{}: {}", extension.name(), err))
+            })
+        }
+
+        fn on_recv(&self, message: Message) -> Result<Message, String> {
+            self.0.iter().rev().try_fold(message, |message, extension| {
+                extension
+                    .on_recv(message)
+                    .map_err(|err| format!("{}: {}", extension.name(), err))
+            })
+        }
+    }
+
+    impl std::fmt::Debug for ExtensionRegistry {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            f.debug_list()
+                .entries(self.0.iter().map(|extension| extension.name()))
+                .finish()
+        }
+    }
+
+    /// An async callback run on every accepted connection after the WebSocket handshake and
+    /// before eventwork starts reading from it, see [`NetworkSettings::handshake_fn`]
+    #[cfg(feature = "handshake")]
+    #[derive(Clone)]
+    pub struct HandshakeFn(
+        Arc<dyn Fn(WebSocketStream<Transport>) -> HandshakeFuture + Send + Sync>,
+    );
+
+    #[cfg(feature = "handshake")]
+    type HandshakeFuture =
+        futures::future::BoxFuture<'static, Result<WebSocketStream<Transport>, NetworkError>>;
+
+    #[cfg(feature = "handshake")]
+    impl HandshakeFn {
+        /// Wraps `handshake`, which may exchange any number of frames before returning the
+        /// stream. Returning an error rejects the connection
+        pub fn new<F, Fut>(handshake: F) -> Self
+        where
+            F: Fn(WebSocketStream<Transport>) -> Fut + Send + Sync + 'static,
+            Fut: Future<Output = Result<WebSocketStream<Transport>, NetworkError>> + Send + 'static,
+        {
+            Self(Arc::new(move |stream| Box::pin(handshake(stream))))
+        }
+    }
+
+    #[cfg(feature = "handshake")]
+    impl std::fmt::Debug for HandshakeFn {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            f.debug_struct("HandshakeFn").finish_non_exhaustive()
+        }
+    }
+
+    /// Delays and drops the packets from `messages` as set by `conditions`
+    ///
+    /// Each packet's delay starts when it is queued, so packets sent together arrive together
+    /// instead of each waiting for the previous one's delay. Packets stay in order, as they
+    /// would over TCP.
+    #[cfg(debug_assertions)]
+    fn simulate_network(
+        messages: Receiver<NetworkPacket>,
+        conditions: MockNetworkConditions,
+    ) -> Receiver<NetworkPacket> {
+        let (queued_sender, queued) = async_channel::unbounded();
+        let (delayed, delayed_receiver) = async_channel::unbounded();
+
+        async_std::task::spawn(async move {
+            while let Ok(message) = messages.recv().await {
+                if fastrand::f32() < conditions.packet_loss {
+                    trace!("Dropping packet to simulate packet loss");
+                    continue;
+                }
+                let delay = conditions.latency_ms + fastrand::u64(0..=conditions.jitter_ms);
+                let deadline = std::time::Instant::now() + Duration::from_millis(delay);
+                if queued_sender.send((deadline, message)).await.is_err() {
+                    break;
+                }
+            }
+        });
+        async_std::task::spawn(async move {
+            while let Ok((deadline, message)) = queued.recv().await {
+                let remaining = deadline.saturating_duration_since(std::time::Instant::now());
+                async_std::task::sleep(remaining).await;
+                if delayed.send(message).await.is_err() {
+                    break;
+                }
+            }
+        });
+
+        delayed_receiver
+    }
+
+    /// Writes `first` and, with [`FlushStrategy::Batched`], any packets following it, then
+    /// flushes them
+    ///
+    /// Returns `false` once the connection can no longer be written to
+    async fn write_batch(
+        write_half: &mut SplitSink<WebSocketStream<Transport>, Message>,
+        first: NetworkPacket,
+        messages: &Receiver<NetworkPacket>,
+        settings: &NetworkSettings,
+    ) -> bool {
+        let mut written = write_packet(write_half, first, settings).await;
+
+        if let FlushStrategy::Batched {
+            max_messages,
+            max_delay_ms,
+        } = settings.flush_strategy
+        {
+            let deadline = std::time::Instant::now() + Duration::from_millis(max_delay_ms);
+            let mut batched = 1;
+            while written && batched < max_messages {
+                // Queued packets are taken right away, only an empty queue waits for more
+                let message = match messages.try_recv() {
+                    Ok(message) => message,
+                    Err(_) => {
+                        let remaining =
+                            deadline.saturating_duration_since(std::time::Instant::now());
+                        if remaining.is_zero() {
+                            break;
+                        }
+                        match async_std::future::timeout(remaining, messages.recv()).await {
+                            Ok(Ok(message)) => message,
+                            _ => break,
+                        }
+                    }
+                };
+                written = write_packet(write_half, message, settings).await;
+                batched += 1;
+            }
+        }
+
+        if !written {
+            return false;
+        }
+        if let Err(err) = write_half.flush().await {
+            error!("Could not flush connection: {}", err);
+            return false;
+        }
+        true
+    }
+
+    /// Encodes and buffers a single packet, without flushing it
+    ///
+    /// Returns `false` once the connection can no longer be written to
+    async fn write_packet(
+        write_half: &mut SplitSink<WebSocketStream<Transport>, Message>,
+        message: NetworkPacket,
+        settings: &NetworkSettings,
+    ) -> bool {
+        let encoded = match bincode::serialize(&message) {
+            Ok(encoded) => encoded,
+            Err(err) => {
+                error!("Could not encode packet {:?}: {}", message, err);
+                return true;
+            }
+        };
+
+        // Recorded before signing, as received sizes are recorded after the tag is stripped
+        #[cfg(feature = "perf-metrics")]
+        settings.size_histogram.record_sent(&encoded);
+
+        #[cfg(feature = "hmac")]
+        let mut encoded = encoded;
+        #[cfg(feature = "hmac")]
+        if let Some(key) = &settings.hmac_key {
+            crate::signing::sign(key, &mut encoded);
+        }
+
+        let outgoing = match settings.extensions.on_send(Message::Binary(encoded)) {
+            Ok(outgoing) => outgoing,
+            Err(err) => {
+                error!("Extension rejected packet {:?}: {}", message, err);
+                return true;
+            }
+        };
+
+        trace!("Sending the content of the message!");
+
+        match write_half.feed(outgoing).await {
+            Ok(_) => (),
+            Err(err) => {
+                error!("Could not send packet: {:?}: {}", message, err);
+                return false;
+            }
+        }
+
+        trace!("Succesfully written all!");
+        true
+    }
+
+    #[derive(Clone, Resource, Default, Deref, DerefMut)]
+    #[allow(missing_copy_implementations)]
+    /// Settings to configure the network, both client and server
+    ///
+    /// Clones share their connection state, such as the peer count and bound address, until
+    /// [`crate::NetworkPlugin`] gives the settings inserted into another app state of their own.
+    /// This lets the same settings be reused by several apps in one process.
+    ///
+    /// The `Debug` output redacts secrets: the HMAC key, proxy credentials and header values.
+    pub struct NetworkSettings {
+        #[deref]
+        config: WebSocketConfig,
+        /// The world whose `NetworkSettings` resource these settings were last adopted as
+        owner: Option<WorldId>,
+        peers: PeerCounter,
+        allowed_kinds: KindFilter,
+        extensions: ExtensionRegistry,
+        shutdown: ShutdownSignal,
+        bound_addr: Arc<Mutex<Option<SocketAddr>>>,
+        upgrade_response: Arc<Mutex<Option<UpgradeResponse>>>,
+        /// When set, every packet is signed with HMAC-SHA256 using this key and packets with a
+        /// missing or invalid tag close the connection
+        ///
+        /// Both ends of a connection must use the same key.
+        #[cfg(feature = "hmac")]
+        pub hmac_key: Option<[u8; 32]>,
+        /// When set, servers accept `wss://` connections using this configuration instead of
+        /// clear text ones
+        ///
+        /// [`crate::tls::load_server_config`] builds one from PEM files.
+        #[cfg(feature = "tls")]
+        pub tls_config: Option<Arc<futures_rustls::rustls::ServerConfig>>,
+        /// When outgoing messages are flushed to the socket
+        pub flush_strategy: FlushStrategy,
+        /// Which addresses a server binds to
+        pub bind_mode: BindMode,
+        /// Sets `SO_REUSEPORT` on server listeners so several servers, such as one per process,
+        /// can share a port. Only supported on Unix
+        pub reuse_port: bool,
+        /// When set, servers answer plain HTTP `GET` requests that do not ask for a WebSocket
+        /// upgrade with `200 OK` and this body, for load balancer health checks
+        ///
+        /// `HEAD` requests get the same headers without the body. Health checks are only
+        /// answered on clear text connections, so this is ignored when `tls_config` is set.
+        pub health_check: Option<String>,
+        /// When set, clients connect through this HTTP proxy with a `CONNECT` tunnel
+        pub proxy: Option<ProxyConfig>,
+        /// When set, servers run this on every accepted connection before handing it to
+        /// eventwork, for example to exchange an authentication challenge
+        ///
+        /// Handshakes of different connections run concurrently, so a slow one does not delay
+        /// the others. This counts towards [`NetworkSettings::handshake_timeout`].
+        #[cfg(feature = "handshake")]
+        pub handshake_fn: Option<HandshakeFn>,
+        /// How long an accepted connection has to finish its handshakes before it is dropped
+        pub handshake_timeout: HandshakeTimeout,
+        /// Extra HTTP headers sent with a client's handshake request, such as `Authorization`
+        pub headers: Vec<(String, String)>,
+        /// Subprotocols for the `Sec-WebSocket-Protocol` handshake header, in order of preference
+        ///
+        /// Clients propose all of them. Servers accept the first one the client proposed that is
+        /// in this list and reject the upgrade with `400 Bad Request` if there is none. When
+        /// empty, clients propose nothing and servers accept any client.
+        pub subprotocols: Vec<String>,
+        /// The most connections a server keeps open at once. `None` accepts without limit
+        ///
+        /// Once the limit is reached new connections wait in the OS backlog until one closes.
+        /// Connections that were accepted but not yet picked up by eventwork are not counted, so
+        /// the limit can briefly be exceeded by a few connections.
+        pub max_connections: Option<usize>,
+        /// How long to wait for connections to close when shutting down on app exit
+        pub shutdown_timeout: ShutdownTimeout,
+        /// Closes a connection that produces more than `count` nonfatal errors within the
+        /// `Duration`, as such a peer is most likely malicious. `None` never closes it
+        ///
+        /// Packets dropped by [`NetworkSettings::allow_message_kinds`] count as errors.
+        pub error_rate_limit: Option<(usize, Duration)>,
+        /// How long a connection may stay silent before it is pinged. `None` never pings
+        ///
+        /// A connection that does not answer the ping within a second is closed. When set this
+        /// takes precedence over [`NetworkSettings::recv_timeout`].
+        pub inactivity_timeout: Option<Duration>,
+        /// How long the recv loop waits for a message before timing out. `None` waits forever
+        ///
+        /// Ignored when [`NetworkSettings::inactivity_timeout`] is set, as that already bounds
+        /// how long the recv loop waits.
+        pub recv_timeout: Option<Duration>,
+        /// Whether a [`NetworkSettings::recv_timeout`] closes the connection or is ignored
+        pub disconnect_on_recv_timeout: bool,
+        /// Simulates a bad network on every outgoing message. Only available in debug builds
+        ///
+        /// Messages still being delayed when the connection shuts down are dropped.
+        #[cfg(debug_assertions)]
+        pub mock_network: Option<MockNetworkConditions>,
+        #[cfg(feature = "perf-metrics")]
+        size_histogram: crate::metrics::MessageSizeHistogram,
+    }
+
+    /// When outgoing messages are flushed to the socket, see [`NetworkSettings::flush_strategy`]
+    #[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+    pub enum FlushStrategy {
+        /// Flushes after every message, for the lowest latency
+        #[default]
+        AfterEachMessage,
+        /// Writes up to `max_messages` messages, waiting at most `max_delay_ms` for more to
+        /// arrive, before flushing them together
+        ///
+        /// Messages that are already queued are written without waiting, so with a
+        /// `max_delay_ms` of 0 no message is delayed and this only saves system calls when many
+        /// small messages are sent at once. A longer delay trades latency for fuller batches.
+        Batched {
+            /// The most messages written before flushing
+            max_messages: usize,
+            /// The longest the first message of a batch waits for others, in milliseconds
+            max_delay_ms: u64,
+        },
+    }
+
+    /// Which addresses a server binds to, see [`NetworkSettings::bind_mode`]
+    #[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+    pub enum BindMode {
+        /// Binds exactly the address passed to `listen`
+        ///
+        /// On most systems binding `[::]` this way also accepts IPv4 connections.
+        #[default]
+        Exact,
+        /// Binds the IPv4 address passed to `listen`, or every IPv4 interface, `0.0.0.0`, on its
+        /// port when given an IPv6 address
+        Ipv4Only,
+        /// Binds the IPv6 address passed to `listen`, or every IPv6 interface, `[::]`, on its
+        /// port when given an IPv4 address. IPv4 connections are refused
+        Ipv6Only,
+        /// Binds both `0.0.0.0` and `[::]` on the port passed to `listen`, whatever its address
+        DualStack,
+    }
+
+    impl BindMode {
+        fn bind(self, addr: SocketAddr, reuse_port: bool) -> std::io::Result<Vec<TcpListener>> {
+            let ipv4 = SocketAddr::from((std::net::Ipv4Addr::UNSPECIFIED, addr.port()));
+            let ipv6 = SocketAddr::from((std::net::Ipv6Addr::UNSPECIFIED, addr.port()));
+            let (addresses, only_v6) = match self {
+                BindMode::Exact => (vec![addr], false),
+                BindMode::Ipv4Only if addr.is_ipv4() => (vec![addr], false),
+                BindMode::Ipv4Only => (vec![ipv4], false),
+                BindMode::Ipv6Only if addr.is_ipv6() => (vec![addr], true),
+                BindMode::Ipv6Only => (vec![ipv6], true),
+                BindMode::DualStack => (vec![ipv4, ipv6], true),
+            };
+            let mut port = addr.port();
+            let mut listeners = Vec::new();
+            for mut address in addresses {
+                // Binding port 0 again would give every listener a different port
+                address.set_port(port);
+                let listener = bind_listener(address, only_v6, reuse_port)?;
+                port = listener.local_addr()?.port();
+                listeners.push(listener);
+            }
+            Ok(listeners)
+        }
+    }
+
+    /// Binds a listener with the socket options std does not expose
+    ///
+    /// `only_v6` sets `IPV6_V6ONLY` on IPv6 listeners, as otherwise an IPv6 wildcard listener
+    /// would claim the IPv4 port too, making a second IPv4 listener on the same port fail.
+    fn bind_listener(
+        addr: SocketAddr,
+        only_v6: bool,
+        reuse_port: bool,
+    ) -> std::io::Result<TcpListener> {
+        let socket = Socket::new(Domain::for_address(addr), Type::STREAM, Some(Protocol::TCP))?;
+        if only_v6 && addr.is_ipv6() {
+            socket.set_only_v6(true)?;
+        }
+        // On Windows SO_REUSEADDR lets another socket steal a port that is already bound
+        #[cfg(unix)]
+        socket.set_reuse_address(true)?;
+        if reuse_port {
+            #[cfg(unix)]
+            socket.set_reuse_port(true)?;
+            #[cfg(not(unix))]
+            warn!("SO_REUSEPORT is not supported on this platform, ignoring reuse_port");
+        }
+        socket.set_nonblocking(true)?;
+        socket.bind(&addr.into())?;
+        socket.listen(LISTEN_BACKLOG)?;
+        Ok(TcpListener::from(std::net::TcpListener::from(socket)))
+    }
+
+    /// The backlog of pending connections for listeners bound through `socket2`
+    const LISTEN_BACKLOG: i32 = 1024;
+
+    impl std::fmt::Debug for NetworkSettings {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            let mut debug = f.debug_struct("NetworkSettings");
+            debug
+                .field("config", &self.config)
+                .field("peers", &self.peers)
+                .field("allowed_kinds", &self.allowed_kinds)
+                .field("extensions", &self.extensions)
+                .field("shutdown", &self.shutdown)
+                .field("bound_addr", &self.bound_addr)
+                .field("upgrade_response", &self.upgrade_response);
+            #[cfg(feature = "hmac")]
+            debug.field("hmac_key", &self.hmac_key.map(|_| REDACTED));
+            #[cfg(feature = "tls")]
+            debug.field("tls_config", &self.tls_config);
+            debug
+                .field("flush_strategy", &self.flush_strategy)
+                .field("bind_mode", &self.bind_mode)
+                .field("reuse_port", &self.reuse_port)
+                .field("health_check", &self.health_check)
+                .field("proxy", &self.proxy);
+            #[cfg(feature = "handshake")]
+            debug.field("handshake_fn", &self.handshake_fn);
+            debug
+                .field("handshake_timeout", &self.handshake_timeout)
+                .field(
+                    "headers",
+                    &self
+                        .headers
+                        .iter()
+                        .map(|(name, _)| (name, REDACTED))
+                        .collect::<Vec<_>>(),
+                )
+                .field("subprotocols", &self.subprotocols)
+                .field("max_connections", &self.max_connections)
+                .field("shutdown_timeout", &self.shutdown_timeout)
+                .field("error_rate_limit", &self.error_rate_limit)
+                .field("inactivity_timeout", &self.inactivity_timeout)
+                .field("recv_timeout", &self.recv_timeout)
+                .field(
+                    "disconnect_on_recv_timeout",
+                    &self.disconnect_on_recv_timeout,
+                );
+            #[cfg(debug_assertions)]
+            debug.field("mock_network", &self.mock_network);
+            #[cfg(feature = "perf-metrics")]
+            debug.field("size_histogram", &self.size_histogram);
+            debug.finish()
+        }
+    }
+
+    /// Shown in place of secrets in `Debug` output
+    const REDACTED: &str = "<redacted>";
+
+    /// An HTTP proxy that clients tunnel through, see [`NetworkSettings::proxy`]
+    ///
+    /// The `Debug` output redacts the password.
+    #[derive(Clone, PartialEq, Eq)]
+    pub struct ProxyConfig {
+        /// The address of the proxy
+        pub addr: SocketAddr,
+        /// The username and password sent with `Proxy-Authorization: Basic`
+        pub credentials: Option<(String, String)>,
+    }
+
+    impl std::fmt::Debug for ProxyConfig {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            f.debug_struct("ProxyConfig")
+                .field("addr", &self.addr)
+                .field(
+                    "credentials",
+                    &self
+                        .credentials
+                        .as_ref()
+                        .map(|(username, _)| (username, REDACTED)),
+                )
+                .finish()
+        }
+    }
+
+    impl ProxyConfig {
+        /// The most bytes of the proxy's response read before giving up
+        const MAX_RESPONSE_SIZE: usize = 8192;
+
+        /// Opens a tunnel to `host:port` through the proxy
+        async fn tunnel(&self, host: &str, port: u16) -> Result<TcpStream, NetworkError> {
+            let io_error =
+                |err: std::io::Error| NetworkError::Error(format!("Proxy Error: {}", err));
+
+            let mut stream = TcpStream::connect(self.addr).await.map_err(io_error)?;
+
+            let mut request = format!("CONNECT {host}:{port} HTTP/1.1\r\nHost: {host}:{port}\r\n");
+            if let Some((username, password)) = &self.credentials {
+                let credentials = BASE64_STANDARD.encode(format!("{}:{}", username, password));
+                request.push_str(&format!("Proxy-Authorization: Basic {}\r\n", credentials));
+            }
+            request.push_str("\r\n");
+            stream
+                .write_all(request.as_bytes())
+                .await
+                .map_err(io_error)?;
+
+            // Read a byte at a time so nothing past the response, which belongs to the tunnel,
+            // is consumed
+            let mut response = Vec::new();
+            while !response.ends_with(b"\r\n\r\n") {
+                if response.len() >= Self::MAX_RESPONSE_SIZE {
+                    return Err(NetworkError::Error(String::from(
+                        "Proxy Error: response too large",
+                    )));
+                }
+                let mut byte = [0];
+                stream.read_exact(&mut byte).await.map_err(io_error)?;
+                response.push(byte[0]);
+            }
+
+            let status_line = String::from_utf8_lossy(&response);
+            let status_line = status_line.lines().next().unwrap_or_default();
+            match status_line.split_whitespace().nth(1) {
+                Some("200") => Ok(stream),
+                _ => Err(NetworkError::Error(format!(
+                    "Proxy Error: tunnel refused with {:?}",
+                    status_line
+                ))),
+            }
+        }
+    }
+
+    /// How long an accepted connection has to finish its handshakes, see
+    /// [`NetworkSettings::handshake_timeout`]
+    ///
+    /// This covers the TLS handshake, the WebSocket upgrade, answering health checks and the
+    /// `handshake_fn`. Without it, clients that connect and never send anything would take up
+    /// the server's pending handshakes until no one else could connect. Defaults to ten seconds.
+    #[derive(Clone, Copy, Debug, PartialEq, Eq, Deref, DerefMut)]
+    pub struct HandshakeTimeout(pub Duration);
+
+    impl Default for HandshakeTimeout {
+        fn default() -> Self {
+            Self(Duration::from_secs(10))
+        }
+    }
+
+    /// Network conditions simulated by the send loop, see [`NetworkSettings::mock_network`]
+    #[cfg(debug_assertions)]
+    #[derive(Clone, Copy, Debug, Default, PartialEq)]
+    pub struct MockNetworkConditions {
+        /// Delay added to every message, in milliseconds
+        pub latency_ms: u64,
+        /// Extra random delay of up to this many milliseconds added on top of the latency
+        pub jitter_ms: u64,
+        /// The chance, from `0.0` to `1.0`, that a message is dropped instead of sent
+        pub packet_loss: f32,
+    }
+
+    impl NetworkSettings {
+        /// Starts building settings from the defaults
+        pub fn builder() -> NetworkSettingsBuilder {
+            NetworkSettingsBuilder::default()
+        }
+
+        /// Settings without any limit on message or frame size
+        ///
+        /// Only use this with trusted peers, as a single message can exhaust memory.
+        pub fn unlimited() -> Self {
+            Self {
+                config: WebSocketConfig {
+                    max_message_size: None,
+                    max_frame_size: None,
+                    ..Default::default()
+                },
+                ..Default::default()
+            }
+        }
+
+        /// Settings suited to a typical game, allowing messages of up to 1 MiB
+        pub fn game_default() -> Self {
+            Self::builder()
+                .max_message_size(1 << 20)
+                .max_frame_size(1 << 20)
+                .build()
+        }
+
+        /// The number of live connections using these settings
+        ///
+        /// Clones of the settings share the same count, so this can be read from the
+        /// `NetworkSettings` resource that was handed to `listen` or `connect`. Settings cloned
+        /// into another app start counting from zero there.
+        pub fn peer_count(&self) -> usize {
+            self.peers.count()
+        }
+
+        /// Whether these settings are the `NetworkSettings` resource of `world`
+        pub(crate) fn is_owned_by(&self, world: WorldId) -> bool {
+            self.owner == Some(world)
+        }
+
+        /// Makes these settings the `NetworkSettings` resource of `world`, giving them their own
+        /// connection state so they no longer share it with the app they were cloned from
+        pub(crate) fn adopt(&mut self, world: WorldId) {
+            self.owner = Some(world);
+            self.peers = PeerCounter::default();
+            self.shutdown = ShutdownSignal::default();
+            self.bound_addr = Arc::default();
+            self.upgrade_response = Arc::default();
+            #[cfg(feature = "perf-metrics")]
+            {
+                self.size_histogram = Default::default();
+            }
+        }
+
+        /// Only accepts packets whose kind is one of the given `NetworkMessage::NAME`s
+        ///
+        /// Packets of any other kind are dropped before they reach eventwork and count towards
+        /// [`NetworkSettings::error_rate_limit`]. By default every kind is accepted.
+        pub fn allow_message_kinds<I, S>(&mut self, kinds: I)
+        where
+            I: IntoIterator<Item = S>,
+            S: Into<String>,
+        {
+            self.allowed_kinds = KindFilter::new(kinds);
+        }
+
+        /// Registers an [`Extension`] applied to every message on connections using these
+        /// settings
+        pub fn register_extension(&mut self, extension: impl Extension) {
+            self.extensions.register(extension);
+        }
+
+        /// The sizes of the messages sent and received using these settings
+        #[cfg(feature = "perf-metrics")]
+        pub fn size_histogram(&self) -> &crate::metrics::MessageSizeHistogram {
+            &self.size_histogram
+        }
+
+        /// Checks that the settings are consistent, returning the first problem found
+        ///
+        /// This is called when listening or connecting, so misconfigured settings fail there
+        /// instead of panicking inside tungstenite later on.
+        pub fn validate(&self) -> Result<(), &'static str> {
+            if let (Some(max_frame_size), Some(max_message_size)) =
+                (self.max_frame_size, self.max_message_size)
+            {
+                if max_frame_size > max_message_size {
+                    return Err("max_frame_size must not be larger than max_message_size");
+                }
+            }
+            if self.max_write_buffer_size <= self.write_buffer_size {
+                return Err("max_write_buffer_size must be larger than write_buffer_size");
+            }
+            if let FlushStrategy::Batched {
+                max_messages: 0, ..
+            } = self.flush_strategy
+            {
+                return Err("FlushStrategy::Batched max_messages must be at least 1");
+            }
+            if let Some((_, Duration::ZERO)) = self.error_rate_limit {
+                return Err("error_rate_limit window must not be zero");
+            }
+            if self.recv_timeout == Some(Duration::ZERO) {
+                return Err("recv_timeout must not be zero");
+            }
+            if self.inactivity_timeout == Some(Duration::ZERO) {
+                return Err("inactivity_timeout must not be zero");
+            }
+            if self.max_connections == Some(0) {
+                return Err("max_connections must be at least 1");
+            }
+            if self.handshake_timeout.is_zero() {
+                return Err("handshake_timeout must not be zero");
+            }
+            Ok(())
+        }
+
+        /// The address the server using these settings is listening on, once it is bound
+        ///
+        /// This is the address picked by the OS when listening on port 0. With
+        /// [`BindMode::DualStack`] it is the IPv4 address.
+        pub fn bound_addr(&self) -> Option<SocketAddr> {
+            *self.bound_addr.lock().expect("Bound address was poisoned")
+        }
+
+        /// Takes the HTTP response to the last handshake made by a client using these settings
+        pub(crate) fn take_upgrade_response(&self) -> Option<UpgradeResponse> {
+            self.upgrade_response
+                .lock()
+                .expect("Upgrade response was poisoned")
+                .take()
+        }
+
+        /// Drains every connection's send queue and closes it, blocking for at most `timeout`
+        ///
+        /// Returns `true` if every connection was closed before the timeout elapsed.
+        pub fn shutdown(&self, timeout: Duration) -> bool {
+            self.shutdown.trigger();
+            async_std::task::block_on(self.shutdown.drained(timeout))
+        }
+    }
+
+    /// Builds [`NetworkSettings`], see [`NetworkSettings::builder`]
+    #[derive(Clone, Debug, Default)]
+    pub struct NetworkSettingsBuilder {
+        settings: NetworkSettings,
+    }
+
+    impl NetworkSettingsBuilder {
+        /// The largest message accepted from a peer, in bytes
+        pub fn max_message_size(mut self, max_message_size: usize) -> Self {
+            self.settings.max_message_size = Some(max_message_size);
+            self
+        }
+
+        /// The largest single frame accepted from a peer, in bytes
+        pub fn max_frame_size(mut self, max_frame_size: usize) -> Self {
+            self.settings.max_frame_size = Some(max_frame_size);
+            self
+        }
+
+        /// How many bytes are buffered before they are written to the socket
+        pub fn write_buffer_size(mut self, write_buffer_size: usize) -> Self {
+            self.settings.write_buffer_size = write_buffer_size;
+            self
+        }
+
+        /// The most bytes that may be buffered while the socket cannot be written to
+        ///
+        /// This replaces tungstenite's deprecated `max_send_queue`.
+        pub fn max_write_buffer_size(mut self, max_write_buffer_size: usize) -> Self {
+            self.settings.max_write_buffer_size = max_write_buffer_size;
+            self
+        }
+
+        /// Finishes building the settings
+        pub fn build(self) -> NetworkSettings {
+            self.settings
+        }
+    }
+
+    /// A connection being accepted by [`OwnedIncoming`]
+    type PendingAccept = Pin<Box<dyn Future<Output = Result<TcpStream, String>> + Send>>;
+
+    /// An accepted connection going through its handshakes in [`OwnedIncoming`]
+    type PendingHandshake =
+        Pin<Box<dyn Future<Output = Result<Option<WebSocketStream<Transport>>, String>> + Send>>;
+
+    /// Resolves once the settings of an [`OwnedIncoming`] start shutting down
+    type ShutdownTriggered = Pin<Box<dyn Future<Output = ()> + Send>>;
+
+    /// A special stream for recieving ws connections
+    ///
+    /// Connections are accepted from every bound listener. Their handshakes run concurrently,
+    /// so a slow client does not hold up the ones accepted after it. Failed accepts and
+    /// handshakes are logged and skipped. The stream ends once the app starts shutting down, so
+    /// no connections are accepted while the others are being closed.
+    pub struct OwnedIncoming {
+        listeners: Vec<(Arc<TcpListener>, Option<PendingAccept>)>,
+        handshakes: FuturesUnordered<PendingHandshake>,
+        settings: Arc<AcceptSettings>,
+        shutdown: ShutdownTriggered,
+    }
+
+    /// The parts of [`NetworkSettings`] needed to accept a connection
+    struct AcceptSettings {
+        config: WebSocketConfig,
+        subprotocols: Vec<String>,
+        extensions: ExtensionRegistry,
+        health_check: Option<String>,
+        handshake_timeout: Duration,
+        max_connections: Option<usize>,
+        peers: PeerCounter,
+        shutdown: ShutdownSignal,
+        #[cfg(feature = "tls")]
+        tls_config: Option<Arc<futures_rustls::rustls::ServerConfig>>,
+        #[cfg(feature = "handshake")]
+        handshake_fn: Option<HandshakeFn>,
+    }
+
+    impl From<&NetworkSettings> for AcceptSettings {
+        fn from(settings: &NetworkSettings) -> Self {
+            Self {
+                config: settings.config,
+                subprotocols: settings.subprotocols.clone(),
+                extensions: settings.extensions.clone(),
+                // Only the TLS handshake can be peeked at before it completes, and a
+                // ClientHello never looks like a finished HTTP request
+                #[cfg(feature = "tls")]
+                health_check: settings
+                    .health_check
+                    .clone()
+                    .filter(|_| settings.tls_config.is_none()),
+                #[cfg(not(feature = "tls"))]
+                health_check: settings.health_check.clone(),
+                handshake_timeout: *settings.handshake_timeout,
+                max_connections: settings.max_connections,
+                peers: settings.peers.clone(),
+                shutdown: settings.shutdown.clone(),
+                #[cfg(feature = "tls")]
+                tls_config: settings.tls_config.clone(),
+                #[cfg(feature = "handshake")]
+                handshake_fn: settings.handshake_fn.clone(),
+            }
+        }
+    }
+
+    impl OwnedIncoming {
+        /// How long to wait before accepting again after the listener returned an error
+        ///
+        /// Errors such as file descriptor exhaustion are returned immediately on every call, so
+        /// retrying without a delay would spin.
+        const ACCEPT_RETRY_DELAY: Duration = Duration::from_millis(100);
+
+        /// The most handshakes run at once, after which no more connections are accepted until
+        /// one finishes or exceeds [`NetworkSettings::handshake_timeout`]
+        const MAX_PENDING_HANDSHAKES: usize = 128;
+
+        fn new(listeners: Vec<TcpListener>, settings: &NetworkSettings) -> Self {
+            let shutdown = settings.shutdown.clone();
+            Self {
+                listeners: listeners
+                    .into_iter()
+                    .map(|listener| (Arc::new(listener), None))
+                    .collect(),
+                handshakes: FuturesUnordered::new(),
+                settings: Arc::new(AcceptSettings::from(settings)),
+                shutdown: Box::pin(async move { shutdown.triggered().await }),
+            }
+        }
+    }
+
+    impl std::fmt::Debug for OwnedIncoming {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            let local_addrs: Vec<_> = self
+                .listeners
+                .iter()
+                .map(|(listener, _)| match listener.local_addr() {
+                    Ok(addr) => addr.to_string(),
+                    Err(_) => String::from("<addr unavailable>"),
+                })
+                .collect();
+            let accepting = self
+                .listeners
+                .iter()
+                .filter(|(_, pending)| pending.is_some())
+                .count();
+            f.debug_struct("OwnedIncoming")
+                .field("local_addrs", &local_addrs)
+                .field("accepting", &accepting)
+                .field("handshaking", &self.handshakes.len())
+                .finish_non_exhaustive()
+        }
+    }
+
+    impl Stream for OwnedIncoming {
+        type Item = WebSocketStream<Transport>;
+
+        fn poll_next(
+            self: Pin<&mut Self>,
+            cx: &mut std::task::Context<'_>,
+        ) -> std::task::Poll<Option<Self::Item>> {
+            let incoming = self.get_mut();
+            // The future is only polled until it first completes
+            if incoming.settings.shutdown.is_triggered() || incoming.shutdown.poll(cx).is_ready() {
+                return std::task::Poll::Ready(None);
+            }
+
+            loop {
+                match incoming.handshakes.poll_next_unpin(cx) {
+                    std::task::Poll::Ready(Some(Ok(Some(stream)))) => {
+                        return std::task::Poll::Ready(Some(stream));
+                    }
+                    std::task::Poll::Ready(Some(Ok(None))) => continue,
+                    std::task::Poll::Ready(Some(Err(err))) => {
+                        error!("{}", err);
+                        continue;
+                    }
+                    // An empty set of handshakes is not the end of the stream
+                    std::task::Poll::Ready(None) | std::task::Poll::Pending => {}
+                }
+
+                let mut accepted_any = false;
+
+                for (listener, pending) in &mut incoming.listeners {
+                    if pending.is_none()
+                        && incoming.handshakes.len() >= OwnedIncoming::MAX_PENDING_HANDSHAKES
+                    {
+                        break;
+                    }
+                    // Also checked for an accept that is already pending, as it was started
+                    // before the connections accepted just before it were counted
+                    if let Some(max_connections) = incoming.settings.max_connections {
+                        if incoming
+                            .settings
+                            .peers
+                            .poll_below(max_connections, cx)
+                            .is_pending()
+                        {
+                            continue;
+                        }
+                    }
+
+                    let accepting = pending
+                        .get_or_insert_with(|| Box::pin(accept_connection(listener.clone())));
+
+                    match accepting.poll(cx) {
+                        std::task::Poll::Ready(Ok(stream)) => {
+                            *pending = None;
+                            incoming.handshakes.push(Box::pin(handshake_connection(
+                                stream,
+                                incoming.settings.clone(),
+                            )));
+                            accepted_any = true;
+                        }
+                        std::task::Poll::Ready(Err(err)) => {
+                            error!("{}", err);
+                            *pending = None;
+                            accepted_any = true;
+                        }
+                        std::task::Poll::Pending => {}
+                    }
+                }
+
+                // Poll the new handshakes and start the next accepts
+                if !accepted_any {
+                    return std::task::Poll::Pending;
+                }
+            }
+        }
+    }
+
+    /// Accepts the next connection from `listener`
+    async fn accept_connection(listener: Arc<TcpListener>) -> Result<TcpStream, String> {
+        match listener.accept().await {
+            Ok((stream, _)) => Ok(stream),
+            Err(err) => {
+                async_std::task::sleep(OwnedIncoming::ACCEPT_RETRY_DELAY).await;
+                Err(format!("Failed to accept connection: {}", err))
+            }
+        }
+    }
+
+    /// Performs the handshakes of an accepted connection, giving up once the handshake timeout
+    /// elapses
+    ///
+    /// Returns `None` if the connection was a health check that has already been answered.
+    async fn handshake_connection(
+        stream: TcpStream,
+        settings: Arc<AcceptSettings>,
+    ) -> Result<Option<WebSocketStream<Transport>>, String> {
+        let timeout = settings.handshake_timeout;
+        async_std::future::timeout(timeout, perform_handshakes(stream, settings))
+            .await
+            .map_err(|_| format!("Handshake did not finish within {:?}", timeout))?
+    }
+
+    async fn perform_handshakes(
+        mut stream: TcpStream,
+        settings: Arc<AcceptSettings>,
+    ) -> Result<Option<WebSocketStream<Transport>>, String> {
+        if let Some(body) = &settings.health_check {
+            if answer_health_check(&mut stream, body)
+                .await
+                .map_err(|err| format!("Failed to answer health check: {}", err))?
+            {
+                return Ok(None);
+            }
+        }
+
+        #[cfg(feature = "tls")]
+        let stream = crate::tls::accept(stream, settings.tls_config.as_ref())
+            .await
+            .map_err(|err| format!("TLS handshake failed: {}", err))?;
+        #[cfg(feature = "tls")]
+        if let Some(name) = stream.peer_common_name() {
+            info!("Verified client certificate of {}", name);
+        }
+        #[cfg(not(feature = "tls"))]
+        let stream = Transport::Plain(stream);
+
+        let negotiate = Negotiate {
+            subprotocols: &settings.subprotocols,
+            extensions: &settings.extensions,
+        };
+        let stream = async_tungstenite::accept_hdr_async_with_config(
+            stream,
+            negotiate,
+            Some(settings.config),
+        )
+        .await
+        .map_err(|err| format!("WebSocket handshake failed: {}", err))?;
+
+        #[cfg(feature = "handshake")]
+        let stream = match &settings.handshake_fn {
+            Some(handshake) => (handshake.0)(stream)
+                .await
+                .map_err(|err| format!("Handshake rejected connection: {}", err))?,
+            None => stream,
+        };
+
+        Ok(Some(stream))
+    }
+
+    /// The most bytes of a request read when checking whether it is a health check
+    const HEALTH_CHECK_MAX_REQUEST_SIZE: usize = 4096;
+
+    /// How long a client has to send its request headers before it is treated as a WebSocket
+    const HEALTH_CHECK_TIMEOUT: Duration = Duration::from_secs(1);
+
+    /// The first byte of a TLS ClientHello, which is a handshake record
+    const TLS_HANDSHAKE_RECORD: u8 = 0x16;
+
+    /// Answers a plain HTTP request without an `Upgrade: websocket` header with `body`
+    ///
+    /// The request is only peeked at, so anything else is left for the WebSocket handshake.
+    /// Returns whether the request was answered.
+    async fn answer_health_check(stream: &mut TcpStream, body: &str) -> std::io::Result<bool> {
+        let mut request = [0; HEALTH_CHECK_MAX_REQUEST_SIZE];
+        let peek_headers = peek_request_headers(stream, &mut request);
+        let headers_size =
+            match async_std::future::timeout(HEALTH_CHECK_TIMEOUT, peek_headers).await {
+                Ok(headers_size) => headers_size?,
+                Err(_) => None,
+            };
+        let Some(headers_size) = headers_size else {
+            return Ok(false);
+        };
+
+        let Ok(headers) = std::str::from_utf8(&request[..headers_size]) else {
+            return Ok(false);
+        };
+        let is_head = headers.starts_with("HEAD ");
+        let is_get = headers.starts_with("GET ") || is_head;
+        let is_upgrade = headers.lines().skip(1).any(|line| {
+            line.split_once(':').is_some_and(|(name, value)| {
+                name.trim().eq_ignore_ascii_case("upgrade")
+                    && value.trim().eq_ignore_ascii_case("websocket")
+            })
+        });
+        if !is_get || is_upgrade {
+            return Ok(false);
+        }
+
+        stream.read_exact(&mut request[..headers_size]).await?;
+        // Responses to HEAD requests describe the body without sending it
+        let response = format!(
+            "HTTP/1.1 200 OK\r\nContent-Type: text/plain\r\nContent-Length: {}\r\n\r\n{}",
+            body.len(),
+            if is_head { "" } else { body }
+        );
+        stream.write_all(response.as_bytes()).await?;
+        stream.close().await?;
+        trace!("Answered health check");
+        Ok(true)
+    }
+
+    /// Peeks at a request until its headers are complete, returning their size
+    ///
+    /// Returns `None` if the connection closed or the headers do not fit in `request`.
+    async fn peek_request_headers(
+        stream: &TcpStream,
+        request: &mut [u8],
+    ) -> std::io::Result<Option<usize>> {
+        loop {
+            let peeked = stream.peek(request).await?;
+            if request[..peeked].first() == Some(&TLS_HANDSHAKE_RECORD) {
+                // A TLS client waits for the server before sending anything resembling HTTP
+                return Ok(None);
+            }
+            if let Some(end) = request[..peeked]
+                .windows(4)
+                .position(|window| window == b"\r\n\r\n")
+            {
+                return Ok(Some(end + 4));
+            }
+            if peeked == 0 || peeked == request.len() {
+                return Ok(None);
+            }
+            // The rest of the headers have not arrived yet
+            async_std::task::sleep(Duration::from_millis(5)).await;
+        }
+    }
+
+    // eventwork polls the accept stream from its task pool, which requires it to be `Unpin`
+    const _: fn() = || {
+        fn assert_send<T: Send + Unpin>() {}
+        assert_send::<OwnedIncoming>();
+    };
+
+    /// Answers the WebSocket upgrade request with the negotiated subprotocol and extensions
+    ///
+    /// This is a [`Callback`] rather than a closure, as closures returning the large
+    /// [`ErrorResponse`] trip `clippy::result_large_err`.
+    struct Negotiate<'a> {
+        subprotocols: &'a [String],
+        extensions: &'a ExtensionRegistry,
+    }
+
+    impl Callback for Negotiate<'_> {
+        fn on_request(
+            self,
+            request: &Request,
+            mut response: Response,
+        ) -> Result<Response, ErrorResponse> {
+            match negotiate_subprotocol(self.subprotocols, request) {
+                Subprotocol::Unused => {}
+                Subprotocol::Picked(protocol) => {
+                    response
+                        .headers_mut()
+                        .insert(SEC_WEBSOCKET_PROTOCOL, protocol);
+                }
+                Subprotocol::Unsupported => {
+                    warn!("Rejecting connection that proposed no supported subprotocol");
+                    return Err(bad_request("No supported subprotocol"));
+                }
+            }
+
+            // Extensions the server does not know, such as the `permessage-deflate` browsers
+            // offer, are declined by leaving them out of the response
+            let extensions = self.extensions.names();
+            let offered: Vec<_> = offered_extensions(request.headers())
+                .into_iter()
+                .filter(|name| extensions.contains(name))
+                .collect();
+            if offered != extensions {
+                warn!("Rejecting connection that did not offer the registered extensions");
+                return Err(bad_request("Extensions do not match the server's"));
+            }
+            if !extensions.is_empty() {
+                let extensions = HeaderValue::from_str(&extensions.join(", "))
+                    .expect("Extension names were matched against a valid header value");
+                response
+                    .headers_mut()
+                    .insert(SEC_WEBSOCKET_EXTENSIONS, extensions);
+            }
+            Ok(response)
+        }
+    }
+
+    fn bad_request(reason: &str) -> ErrorResponse {
+        let mut error = ErrorResponse::new(Some(String::from(reason)));
+        *error.status_mut() = StatusCode::BAD_REQUEST;
+        error
+    }
 
-    /// A provider for WebSockets
-    #[derive(Default, Debug)]
-    pub struct NativeWesocketProvider;
+    /// The extension names listed in the `Sec-WebSocket-Extensions` headers, without their
+    /// parameters
+    fn offered_extensions(headers: &HeaderMap) -> Vec<&str> {
+        headers
+            .get_all(SEC_WEBSOCKET_EXTENSIONS)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .map(|extension| extension.split(';').next().unwrap_or_default().trim())
+            .filter(|name| !name.is_empty())
+            .collect()
+    }
 
-    #[cfg_attr(not(target_arch = "wasm32"), async_trait)]
-    #[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
-    impl NetworkProvider for NativeWesocketProvider {
-        type NetworkSettings = NetworkSettings;
+    /// The outcome of [`negotiate_subprotocol`]
+    #[derive(Debug, PartialEq, Eq)]
+    enum Subprotocol {
+        /// The server has no subprotocols configured, so none is sent back
+        Unused,
+        /// The subprotocol to send back to the client
+        Picked(HeaderValue),
+        /// The client proposed no subprotocol the server supports
+        Unsupported,
+    }
 
-        type Socket = WebSocketStream<TcpStream>;
+    /// Picks the first subprotocol proposed by the client that the server supports
+    fn negotiate_subprotocol(supported: &[String], request: &Request) -> Subprotocol {
+        if supported.is_empty() {
+            return Subprotocol::Unused;
+        }
 
-        type ReadHalf = SplitStream<WebSocketStream<TcpStream>>;
+        let proposed = request
+            .headers()
+            .get_all(SEC_WEBSOCKET_PROTOCOL)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .map(str::trim);
 
-        type WriteHalf = SplitSink<WebSocketStream<TcpStream>, Message>;
+        for protocol in proposed {
+            if supported.iter().any(|supported| supported == protocol) {
+                let protocol = HeaderValue::from_str(protocol)
+                    .expect("Protocol was read from a valid header value");
+                return Subprotocol::Picked(protocol);
+            }
+        }
 
-        type ConnectInfo = url::Url;
+        Subprotocol::Unsupported
+    }
 
-        type AcceptInfo = SocketAddr;
+    /// How a [`NetworkLoadBalancer`] picks the backend for a new connection
+    #[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+    pub enum BalancingPolicy {
+        /// Hand out backends in turn
+        #[default]
+        RoundRobin,
+        /// Pick the backend with the fewest live connections
+        LeastConnections,
+    }
 
-        type AcceptStream = OwnedIncoming;
+    /// Accepts TCP connections and forwards them to one of several backend servers
+    ///
+    /// Bytes are copied both ways without being decoded, so the WebSocket handshake, including
+    /// its headers and subprotocols, TLS and close codes all reach the backend unchanged.
+    ///
+    /// [`NetworkLoadBalancer::run`] consumes the balancer, so keep a clone to read
+    /// [`NetworkLoadBalancer::connection_count`] from. Clones share their connection counts.
+    #[derive(Clone, Debug)]
+    pub struct NetworkLoadBalancer {
+        backends: Vec<SocketAddr>,
+        policy: BalancingPolicy,
+        max_connections: Option<usize>,
+        next_backend: Arc<AtomicUsize>,
+        /// The live connections of each backend, in the same order as `backends`
+        connections: Vec<PeerCounter>,
+        /// The live connections across all backends
+        total_connections: PeerCounter,
+    }
+
+    impl NetworkLoadBalancer {
+        /// Creates a balancer over the given backends
+        pub fn new(backends: Vec<SocketAddr>, policy: BalancingPolicy) -> Self {
+            Self {
+                connections: backends.iter().map(|_| PeerCounter::default()).collect(),
+                backends,
+                policy,
+                max_connections: None,
+                next_backend: Arc::default(),
+                total_connections: PeerCounter::default(),
+            }
+        }
+
+        /// Forwards at most `max_connections` connections at once
+        ///
+        /// Once the limit is reached new connections wait in the OS backlog until one closes.
+        pub fn with_max_connections(mut self, max_connections: usize) -> Self {
+            self.max_connections = Some(max_connections);
+            self
+        }
+
+        /// The number of live connections forwarded to the given backend
+        pub fn connection_count(&self, backend: SocketAddr) -> usize {
+            self.backends
+                .iter()
+                .position(|addr| *addr == backend)
+                .map_or(0, |index| self.connections[index].count())
+        }
+
+        /// Listens on `accept_info` and forwards connections to the backends
+        ///
+        /// This only returns if there are no backends or `accept_info` cannot be bound,
+        /// otherwise it keeps accepting until the future is dropped.
+        pub async fn run(self, accept_info: SocketAddr) -> Result<(), NetworkError> {
+            if self.backends.is_empty() {
+                return Err(NetworkError::Error(String::from(
+                    "Load balancer needs at least one backend",
+                )));
+            }
+            if self.max_connections == Some(0) {
+                return Err(NetworkError::Error(String::from(
+                    "Load balancer max_connections must be at least 1",
+                )));
+            }
 
-        async fn accept_loop(
-            accept_info: Self::AcceptInfo,
-            _: Self::NetworkSettings,
-        ) -> Result<Self::AcceptStream, NetworkError> {
             let listener = TcpListener::bind(accept_info)
                 .await
                 .map_err(NetworkError::Listen)?;
-            Ok(OwnedIncoming::new(listener))
-        }
+            info!("Load balancer listening on {}", accept_info);
 
-        async fn connect_task(
-            connect_info: Self::ConnectInfo,
-            network_settings: Self::NetworkSettings,
-        ) -> Result<Self::Socket, NetworkError> {
-            info!("Beginning connection");
-            let (stream, _response) = async_tungstenite::async_std::connect_async_with_config(
-                connect_info,
-                Some(*network_settings),
-            )
-            .await
-            .map_err(|error| match error {
-                async_tungstenite::tungstenite::Error::ConnectionClosed => {
-                    NetworkError::Error(String::from("Connection closed"))
-                }
-                async_tungstenite::tungstenite::Error::AlreadyClosed => {
-                    NetworkError::Error(String::from("Connection was already closed"))
-                }
-                async_tungstenite::tungstenite::Error::Io(io_error) => {
-                    NetworkError::Error(format!("Io Error: {}", io_error))
-                }
-                async_tungstenite::tungstenite::Error::Tls(tls_error) => {
-                    NetworkError::Error(format!("Tls Error: {}", tls_error))
-                }
-                async_tungstenite::tungstenite::Error::Capacity(cap) => {
-                    NetworkError::Error(format!("Capacity Error: {}", cap))
-                }
-                async_tungstenite::tungstenite::Error::Protocol(proto) => {
-                    NetworkError::Error(format!("Protocol Error: {}", proto))
-                }
-                async_tungstenite::tungstenite::Error::WriteBufferFull(buf) => {
-                    NetworkError::Error(format!("Write Buffer Full Error: {}", buf))
-                }
-                async_tungstenite::tungstenite::Error::Utf8 => {
-                    NetworkError::Error("Utf8 Error".to_string())
-                }
-                async_tungstenite::tungstenite::Error::AttackAttempt => {
-                    NetworkError::Error("Attack Attempt".to_string())
-                }
-                async_tungstenite::tungstenite::Error::Url(url) => {
-                    NetworkError::Error(format!("Url Error: {}", url))
-                }
-                async_tungstenite::tungstenite::Error::Http(http) => {
-                    NetworkError::Error(format!("HTTP Error: {:?}", http))
-                }
-                async_tungstenite::tungstenite::Error::HttpFormat(http_format) => {
-                    NetworkError::Error(format!("HTTP Format Error: {}", http_format))
+            loop {
+                if let Some(max_connections) = self.max_connections {
+                    futures::future::poll_fn(|cx| {
+                        self.total_connections.poll_below(max_connections, cx)
+                    })
+                    .await;
                 }
-            })?;
-            info!("Connected!");
-            return Ok(stream);
-        }
 
-        async fn recv_loop(
-            mut read_half: Self::ReadHalf,
-            messages: Sender<NetworkPacket>,
-            _settings: Self::NetworkSettings,
-        ) {
-            loop {
-                let message = match read_half.next().await {
-                    Some(message) => match message {
-                        Ok(message) => message,
-                        Err(err) => match err {
-                            async_tungstenite::tungstenite::Error::ConnectionClosed
-                            | async_tungstenite::tungstenite::Error::AlreadyClosed => {
-                                error!("Connection Closed");
-                                break;
-                            }
-                            _ => {
-                                error!("Nonfatal error detected: {}", err);
-                                continue;
-                            }
-                        },
-                    },
-                    None => {
+                let client = match listener.accept().await {
+                    Ok((client, _)) => client,
+                    Err(err) => {
+                        error!("Failed to accept connection: {}", err);
+                        async_std::task::sleep(OwnedIncoming::ACCEPT_RETRY_DELAY).await;
                         continue;
                     }
                 };
 
-                let packet = match message {
-                    Message::Text(_) => {
-                        error!("Text Message Received");
-                        break;
-                    }
-                    Message::Binary(binary) => match bincode::deserialize(&binary) {
-                        Ok(packet) => packet,
-                        Err(err) => {
-                            error!("Failed to decode network packet from: {}", err);
-                            break;
-                        }
-                    },
-                    Message::Ping(_) => {
-                        error!("Ping Message Received");
-                        break;
-                    }
-                    Message::Pong(_) => {
-                        error!("Pong Message Received");
-                        break;
-                    }
-                    Message::Close(_) => {
-                        error!("Connection Closed");
-                        break;
+                let index = self.pick_backend();
+                let backend = self.backends[index];
+                let backend_guard = self.connections[index].track();
+                let peer_guard = self.total_connections.track();
+                async_std::task::spawn(async move {
+                    if let Err(err) = forward(client, backend).await {
+                        error!("Could not forward connection to {}: {}", backend, err);
                     }
-                    Message::Frame(_) => todo!(),
-                };
+                    drop((backend_guard, peer_guard));
+                });
+            }
+        }
 
-                if messages.send(packet).await.is_err() {
-                    error!("Failed to send decoded message to eventwork");
-                    break;
+        /// The index of the backend the next connection is forwarded to
+        fn pick_backend(&self) -> usize {
+            match self.policy {
+                BalancingPolicy::RoundRobin => {
+                    self.next_backend.fetch_add(1, Ordering::Relaxed) % self.backends.len()
                 }
-                info!("Message deserialized and sent to eventwork");
+                BalancingPolicy::LeastConnections => (0..self.backends.len())
+                    .min_by_key(|index| self.connections[*index].count())
+                    .expect("Load balancer has at least one backend"),
             }
         }
+    }
 
-        async fn send_loop(
-            mut write_half: Self::WriteHalf,
-            messages: Receiver<NetworkPacket>,
-            _settings: Self::NetworkSettings,
-        ) {
-            while let Ok(message) = messages.recv().await {
-                let encoded = match bincode::serialize(&message) {
-                    Ok(encoded) => encoded,
-                    Err(err) => {
-                        error!("Could not encode packet {:?}: {}", message, err);
-                        continue;
-                    }
-                };
+    /// Copies bytes between `client` and a new connection to `backend` until both sides close
+    async fn forward(client: TcpStream, backend: SocketAddr) -> std::io::Result<()> {
+        let server = TcpStream::connect(backend).await?;
 
-                trace!("Sending the content of the message!");
+        // Each direction is shut down once the other side stops writing, so half-closed
+        // connections and the close handshake behave as if there was no balancer in between
+        let client_to_server = async {
+            async_std::io::copy(&mut &client, &mut &server).await?;
+            server.shutdown(Shutdown::Write)
+        };
+        let server_to_client = async {
+            async_std::io::copy(&mut &server, &mut &client).await?;
+            client.shutdown(Shutdown::Write)
+        };
+        futures::future::try_join(client_to_server, server_to_client)
+            .await
+            .map(|_| ())
+    }
 
-                match write_half
-                    .send(async_tungstenite::tungstenite::Message::Binary(encoded))
-                    .await
-                {
-                    Ok(_) => (),
-                    Err(err) => {
-                        error!("Could not send packet: {:?}: {}", message, err);
-                        break;
-                    }
-                }
+    #[cfg(test)]
+    mod tests {
+        use super::*;
 
-                trace!("Succesfully written all!");
+        fn request(protocols: &[&str]) -> Request {
+            let mut request = Request::builder();
+            for protocol in protocols {
+                request = request.header(SEC_WEBSOCKET_PROTOCOL, *protocol);
             }
+            request.body(()).unwrap()
         }
 
-        fn split(combined: Self::Socket) -> (Self::ReadHalf, Self::WriteHalf) {
-            let (write, read) = combined.split();
-            (read, write)
+        fn supported(protocols: &[&str]) -> Vec<String> {
+            protocols
+                .iter()
+                .map(|protocol| protocol.to_string())
+                .collect()
         }
-    }
 
-    #[derive(Clone, Debug, Resource, Default, Deref, DerefMut)]
-    #[allow(missing_copy_implementations)]
-    /// Settings to configure the network, both client and server
-    pub struct NetworkSettings(WebSocketConfig);
+        #[test]
+        fn subprotocols_are_unused_without_supported_ones() {
+            assert_eq!(
+                negotiate_subprotocol(&[], &request(&["chat"])),
+                Subprotocol::Unused
+            );
+        }
 
-    /// A special stream for recieving ws connections
-    #[allow(clippy::type_complexity)]
-    pub struct OwnedIncoming {
-        inner: TcpListener,
-        stream: Option<Pin<Box<dyn Future<Output = Option<WebSocketStream<TcpStream>>>>>>,
-    }
+        #[test]
+        fn picks_first_proposed_supported_subprotocol() {
+            let supported = supported(&["v1", "v2"]);
+            assert_eq!(
+                negotiate_subprotocol(&supported, &request(&["v3, v2", "v1"])),
+                Subprotocol::Picked(HeaderValue::from_static("v2"))
+            );
+        }
 
-    impl OwnedIncoming {
-        fn new(listener: TcpListener) -> Self {
-            Self {
-                inner: listener,
-                stream: None,
+        #[test]
+        fn rejects_unsupported_subprotocols() {
+            let supported = supported(&["v1"]);
+            assert_eq!(
+                negotiate_subprotocol(&supported, &request(&["v2"])),
+                Subprotocol::Unsupported
+            );
+            assert_eq!(
+                negotiate_subprotocol(&supported, &request(&[])),
+                Subprotocol::Unsupported
+            );
+        }
+
+        struct Named(&'static str);
+
+        impl Extension for Named {
+            fn name(&self) -> &str {
+                self.0
+            }
+
+            fn on_send(&self, message: Message) -> Result<Message, String> {
+                Ok(message)
+            }
+
+            fn on_recv(&self, message: Message) -> Result<Message, String> {
+                Ok(message)
             }
         }
-    }
 
-    impl Stream for OwnedIncoming {
-        type Item = WebSocketStream<TcpStream>;
+        fn registry(names: &[&'static str]) -> ExtensionRegistry {
+            let mut registry = ExtensionRegistry::default();
+            for name in names {
+                registry.register(Named(name));
+            }
+            registry
+        }
 
-        fn poll_next(
-            self: Pin<&mut Self>,
-            cx: &mut std::task::Context<'_>,
-        ) -> std::task::Poll<Option<Self::Item>> {
-            let incoming = self.get_mut();
-            if incoming.stream.is_none() {
-                let listener: *const TcpListener = &incoming.inner;
-                incoming.stream = Some(Box::pin(async move {
-                    let stream = unsafe {
-                        listener
-                            .as_ref()
-                            .expect("Segfault when trying to read listener in OwnedStream")
-                    }
-                    .accept()
-                    .await
-                    .map(|(s, _)| s)
-                    .ok();
-
-                    let stream: WebSocketStream<TcpStream> = match stream {
-                        Some(stream) => async_tungstenite::accept_async(stream).await.ok()?,
-                        None => return None,
-                    };
-                    Some(stream)
-                }));
-            }
-            if let Some(stream) = &mut incoming.stream {
-                if let std::task::Poll::Ready(res) = stream.poll(cx) {
-                    incoming.stream = None;
-                    return std::task::Poll::Ready(res);
-                }
+        /// Runs the server's negotiation on a request offering `offered` extensions, returning
+        /// the extensions header of the response
+        fn negotiate_extensions(
+            registered: &[&'static str],
+            offered: Option<&str>,
+        ) -> Result<Option<String>, StatusCode> {
+            let mut request = Request::builder();
+            if let Some(offered) = offered {
+                request = request.header(SEC_WEBSOCKET_EXTENSIONS, offered);
             }
-            std::task::Poll::Pending
+            let negotiate = Negotiate {
+                subprotocols: &[],
+                extensions: &registry(registered),
+            };
+            negotiate
+                .on_request(&request.body(()).unwrap(), Response::default())
+                .map(|response| {
+                    response
+                        .headers()
+                        .get(SEC_WEBSOCKET_EXTENSIONS)
+                        .map(|value| value.to_str().unwrap().to_owned())
+                })
+                .map_err(|error| error.status())
         }
-    }
 
-    unsafe impl Send for OwnedIncoming {}
+        #[test]
+        fn parses_offered_extensions() {
+            let mut headers = HeaderMap::new();
+            headers.append(
+                SEC_WEBSOCKET_EXTENSIONS,
+                HeaderValue::from_static("permessage-deflate; client_max_window_bits, lz4"),
+            );
+            headers.append(SEC_WEBSOCKET_EXTENSIONS, HeaderValue::from_static("x"));
+            assert_eq!(
+                offered_extensions(&headers),
+                ["permessage-deflate", "lz4", "x"]
+            );
+        }
+
+        #[test]
+        fn accepts_matching_extensions() {
+            assert_eq!(
+                negotiate_extensions(&["lz4", "x"], Some("lz4, x")),
+                Ok(Some(String::from("lz4, x")))
+            );
+        }
+
+        #[test]
+        fn declines_unknown_extensions() {
+            assert_eq!(
+                negotiate_extensions(&[], Some("permessage-deflate")),
+                Ok(None)
+            );
+            assert_eq!(
+                negotiate_extensions(&["lz4"], Some("permessage-deflate, lz4")),
+                Ok(Some(String::from("lz4")))
+            );
+        }
+
+        #[test]
+        fn rejects_missing_or_reordered_extensions() {
+            assert_eq!(
+                negotiate_extensions(&["lz4"], None),
+                Err(StatusCode::BAD_REQUEST)
+            );
+            assert_eq!(
+                negotiate_extensions(&["lz4", "x"], Some("x, lz4")),
+                Err(StatusCode::BAD_REQUEST)
+            );
+        }
+
+        /// The addresses `mode` binds when listening on `addr`
+        fn bound_addresses(mode: BindMode, addr: &str) -> Vec<SocketAddr> {
+            mode.bind(addr.parse().unwrap(), false)
+                .unwrap()
+                .iter()
+                .map(|listener| listener.local_addr().unwrap())
+                .collect()
+        }
+
+        fn ips(addresses: &[SocketAddr]) -> Vec<String> {
+            addresses.iter().map(|addr| addr.ip().to_string()).collect()
+        }
+
+        #[test]
+        fn exact_binds_the_given_address() {
+            assert_eq!(
+                ips(&bound_addresses(BindMode::Exact, "127.0.0.1:0")),
+                ["127.0.0.1"]
+            );
+            assert_eq!(ips(&bound_addresses(BindMode::Exact, "[::1]:0")), ["::1"]);
+        }
+
+        #[test]
+        fn ipv4_only_keeps_ipv4_addresses() {
+            assert_eq!(
+                ips(&bound_addresses(BindMode::Ipv4Only, "127.0.0.1:0")),
+                ["127.0.0.1"]
+            );
+            assert_eq!(
+                ips(&bound_addresses(BindMode::Ipv4Only, "[::1]:0")),
+                ["0.0.0.0"]
+            );
+        }
+
+        #[test]
+        fn ipv6_only_keeps_ipv6_addresses() {
+            assert_eq!(
+                ips(&bound_addresses(BindMode::Ipv6Only, "[::1]:0")),
+                ["::1"]
+            );
+            assert_eq!(
+                ips(&bound_addresses(BindMode::Ipv6Only, "127.0.0.1:0")),
+                ["::"]
+            );
+        }
+
+        #[test]
+        fn dual_stack_binds_both_wildcards_on_one_port() {
+            let addresses = bound_addresses(BindMode::DualStack, "127.0.0.1:0");
+            assert_eq!(ips(&addresses), ["0.0.0.0", "::"]);
+            assert_eq!(addresses[0].port(), addresses[1].port());
+        }
+
+        #[test]
+        fn presets_are_valid() {
+            assert_eq!(NetworkSettings::default().validate(), Ok(()));
+            assert_eq!(NetworkSettings::unlimited().validate(), Ok(()));
+            assert_eq!(NetworkSettings::game_default().validate(), Ok(()));
+        }
+
+        #[test]
+        fn rejects_frames_larger_than_messages() {
+            let settings = NetworkSettings::builder()
+                .max_message_size(1024)
+                .max_frame_size(2048)
+                .build();
+            assert!(settings.validate().is_err());
+        }
+
+        #[test]
+        fn rejects_write_buffer_not_below_its_maximum() {
+            let settings = NetworkSettings::builder()
+                .write_buffer_size(1024)
+                .max_write_buffer_size(1024)
+                .build();
+            assert!(settings.validate().is_err());
+        }
+
+        #[test]
+        fn rejects_zero_values() {
+            let invalid: [fn(&mut NetworkSettings); 6] = [
+                |settings| {
+                    settings.flush_strategy = FlushStrategy::Batched {
+                        max_messages: 0,
+                        max_delay_ms: 0,
+                    }
+                },
+                |settings| settings.error_rate_limit = Some((10, Duration::ZERO)),
+                |settings| settings.recv_timeout = Some(Duration::ZERO),
+                |settings| settings.inactivity_timeout = Some(Duration::ZERO),
+                |settings| settings.max_connections = Some(0),
+                |settings| settings.handshake_timeout = HandshakeTimeout(Duration::ZERO),
+            ];
+            for make_invalid in invalid {
+                let mut settings = NetworkSettings::default();
+                make_invalid(&mut settings);
+                assert!(settings.validate().is_err(), "{:?}", settings);
+            }
+        }
+
+        #[test]
+        fn debug_output_redacts_secrets() {
+            let settings = NetworkSettings {
+                headers: vec![(String::from("Authorization"), String::from("hunter2"))],
+                proxy: Some(ProxyConfig {
+                    addr: SocketAddr::from(([127, 0, 0, 1], 8080)),
+                    credentials: Some((String::from("user"), String::from("swordfish"))),
+                }),
+                #[cfg(feature = "hmac")]
+                hmac_key: Some([171; 32]),
+                ..Default::default()
+            };
+
+            let debug = format!("{:?}", settings);
+            assert!(debug.contains("Authorization"));
+            assert!(debug.contains("user"));
+            assert!(!debug.contains("hunter2"));
+            assert!(!debug.contains("swordfish"));
+            assert!(!debug.contains("171, 171"));
+        }
+
+        fn adopted_clone(settings: &NetworkSettings) -> NetworkSettings {
+            let mut clone = settings.clone();
+            clone.adopt(bevy::ecs::world::World::new().id());
+            clone
+        }
+
+        #[test]
+        fn adopting_keeps_the_configuration() {
+            let mut settings = NetworkSettings::builder()
+                .max_message_size(4096)
+                .max_frame_size(1024)
+                .build();
+            settings.allow_message_kinds(["chat"]);
+            settings.flush_strategy = FlushStrategy::Batched {
+                max_messages: 8,
+                max_delay_ms: 5,
+            };
+            settings.bind_mode = BindMode::Ipv6Only;
+            settings.reuse_port = true;
+            settings.health_check = Some(String::from("ok"));
+            settings.headers = vec![(String::from("X-Version"), String::from("3"))];
+            settings.subprotocols = vec![String::from("game.v1")];
+            settings.max_connections = Some(16);
+            settings.shutdown_timeout = ShutdownTimeout(Duration::from_secs(3));
+            settings.error_rate_limit = Some((5, Duration::from_secs(1)));
+            settings.inactivity_timeout = Some(Duration::from_secs(20));
+            settings.recv_timeout = Some(Duration::from_secs(30));
+            settings.disconnect_on_recv_timeout = true;
+            settings.handshake_timeout = HandshakeTimeout(Duration::from_secs(4));
+
+            // Every field is part of the `Debug` output
+            assert_eq!(
+                format!("{:?}", adopted_clone(&settings)),
+                format!("{:?}", settings)
+            );
+        }
+
+        #[test]
+        fn adopting_resets_the_connection_state() {
+            let settings = NetworkSettings::default();
+            let _peer = settings.peers.track();
+            *settings.bound_addr.lock().unwrap() = Some(SocketAddr::from(([127, 0, 0, 1], 80)));
+            let clone = adopted_clone(&settings);
+            settings.shutdown.trigger();
+
+            assert_eq!(settings.peer_count(), 1);
+            assert_eq!(clone.peer_count(), 0);
+            assert_eq!(clone.bound_addr(), None);
+            assert!(!clone.shutdown.is_triggered());
+            assert!(settings.clone().shutdown.is_triggered());
+        }
+    }
 }
 
 #[cfg(target_arch = "wasm32")]
@@ -281,7 +2238,10 @@ mod wasm_websocket {
 
     use async_channel::{Receiver, Sender};
     use async_trait::async_trait;
-    use bevy::prelude::{error, info, trace, Deref, DerefMut, Resource};
+    use bevy::{
+        ecs::world::WorldId,
+        prelude::{error, info, trace, warn, Deref, DerefMut, Resource},
+    };
     use bevy_eventwork::{error::NetworkError, managers::NetworkProvider, NetworkPacket};
     use futures::{
         stream::{SplitSink, SplitStream},
@@ -291,6 +2251,12 @@ mod wasm_websocket {
     use send_wrapper::SendWrapper;
     use tokio_tungstenite_wasm::{Message, WebSocketStream};
 
+    use crate::{
+        packet::{packet_kind, KindFilter},
+        peers::PeerCounter,
+        throttle::LogThrottle,
+    };
+
     /// A provider for WebSockets
     #[derive(Default, Debug)]
     pub struct WasmWebSocketProvider;
@@ -376,8 +2342,11 @@ mod wasm_websocket {
         async fn recv_loop(
             mut read_half: Self::ReadHalf,
             messages: Sender<NetworkPacket>,
-            _settings: Self::NetworkSettings,
+            settings: Self::NetworkSettings,
         ) {
+            let _peer = settings.peers.track();
+            let mut log_throttle = LogThrottle::new("the server");
+
             loop {
                 let message = match read_half.next().await {
                     Some(message) => match message {
@@ -388,29 +2357,39 @@ mod wasm_websocket {
                                 error!("Connection Closed");
                                 break;
                             }
+                            tokio_tungstenite_wasm::Error::BlobFormatUnsupported => {
+                                if log_throttle.allow() {
+                                    error!("Blob message received, the server must send binary messages as ArrayBuffer");
+                                }
+                                continue;
+                            }
+                            tokio_tungstenite_wasm::Error::UnknownFormat => {
+                                // The read half cannot send a close frame with code 1007, so the
+                                // connection is closed by ending the loop instead
+                                error!("Message in an unknown format received, closing connection");
+                                break;
+                            }
                             _ => {
-                                error!("Nonfatal error detected: {}", err);
+                                if log_throttle.allow() {
+                                    error!("Nonfatal error detected: {}", err);
+                                }
                                 continue;
                             }
                         },
                     },
                     None => {
-                        continue;
+                        // The stream only ends once the connection has been torn down
+                        trace!("Stream exhausted, closing recv loop");
+                        break;
                     }
                 };
 
-                let packet = match message {
+                let binary = match message {
                     Message::Text(_) => {
                         error!("Text Message Received");
                         break;
                     }
-                    Message::Binary(binary) => match bincode::deserialize(&binary) {
-                        Ok(packet) => packet,
-                        Err(err) => {
-                            error!("Failed to decode network packet from: {}", err);
-                            break;
-                        }
-                    },
+                    Message::Binary(binary) => binary,
 
                     Message::Close(_) => {
                         error!("Connection Closed");
@@ -418,6 +2397,48 @@ mod wasm_websocket {
                     }
                 };
 
+                if binary.len() > settings.max_message_size {
+                    if log_throttle.allow() {
+                        warn!(
+                            "Dropping message of {} bytes, larger than the maximum of {}",
+                            binary.len(),
+                            settings.max_message_size
+                        );
+                    }
+                    continue;
+                }
+
+                #[cfg(feature = "hmac")]
+                let mut binary = binary;
+                #[cfg(feature = "hmac")]
+                if let Some(key) = &settings.hmac_key {
+                    if !crate::signing::verify(key, &mut binary) {
+                        error!("Packet failed HMAC verification, closing connection");
+                        break;
+                    }
+                }
+
+                if !settings.allowed_kinds.allows(&binary) {
+                    if log_throttle.allow() {
+                        warn!(
+                            "Dropping packet with unregistered kind {:?}",
+                            packet_kind(&binary)
+                        );
+                    }
+                    continue;
+                }
+
+                let packet = match bincode::deserialize(&binary) {
+                    Ok(packet) => packet,
+                    Err(err) => {
+                        error!("Failed to decode network packet from: {}", err);
+                        break;
+                    }
+                };
+
+                #[cfg(feature = "perf-metrics")]
+                settings.size_histogram.record_received(&binary);
+
                 if messages.send(packet).await.is_err() {
                     error!("Failed to send decoded message to eventwork");
                     break;
@@ -429,7 +2450,7 @@ mod wasm_websocket {
         async fn send_loop(
             mut write_half: Self::WriteHalf,
             messages: Receiver<NetworkPacket>,
-            _settings: Self::NetworkSettings,
+            settings: Self::NetworkSettings,
         ) {
             while let Ok(message) = messages.recv().await {
                 let encoded = match bincode::serialize(&message) {
@@ -440,6 +2461,20 @@ mod wasm_websocket {
                     }
                 };
 
+                // Recorded before signing, as received sizes are recorded after the tag is
+                // stripped
+                #[cfg(feature = "perf-metrics")]
+                settings.size_histogram.record_sent(&encoded);
+                #[cfg(not(any(feature = "hmac", feature = "perf-metrics")))]
+                let _ = &settings;
+
+                #[cfg(feature = "hmac")]
+                let mut encoded = encoded;
+                #[cfg(feature = "hmac")]
+                if let Some(key) = &settings.hmac_key {
+                    crate::signing::sign(key, &mut encoded);
+                }
+
                 trace!("Sending the content of the message!");
 
                 match write_half
@@ -463,21 +2498,134 @@ mod wasm_websocket {
         }
     }
 
-    #[derive(Clone, Debug, Resource, Deref, DerefMut)]
+    #[derive(Clone, Resource, Deref, DerefMut)]
     #[allow(missing_copy_implementations)]
     /// Settings to configure the network
     ///
-    /// Note that on WASM this is currently ignored and defaults are used
+    /// The browser's `WebSocket` API exposes no connection options, so these are applied by the
+    /// provider after messages are received. The `Debug` output redacts the HMAC key.
     pub struct NetworkSettings {
-        max_message_size: usize,
+        /// The largest message accepted from the server, in bytes. Larger messages are dropped
+        #[deref]
+        pub max_message_size: usize,
+        /// Placeholder for requesting `permessage-deflate` compression
+        ///
+        /// Browsers negotiate `permessage-deflate` on their own and the `WebSocket` API gives no
+        /// way to control it, so `tokio_tungstenite_wasm` cannot expose this. The value is
+        /// ignored. Upstream discussion of compression support:
+        /// <https://github.com/TannerRogalsky/tokio-tungstenite-wasm/issues?q=deflate>.
+        #[deprecated(
+            note = "browsers negotiate permessage-deflate themselves, this is ignored. See https://github.com/TannerRogalsky/tokio-tungstenite-wasm/issues?q=deflate"
+        )]
+        pub allow_deflate: bool,
+        /// When set, every packet is signed with HMAC-SHA256 using this key and packets with a
+        /// missing or invalid tag close the connection
+        ///
+        /// Both ends of a connection must use the same key.
+        #[cfg(feature = "hmac")]
+        pub hmac_key: Option<[u8; 32]>,
+        /// The world whose `NetworkSettings` resource these settings were last adopted as
+        owner: Option<WorldId>,
+        peers: PeerCounter,
+        allowed_kinds: KindFilter,
+        #[cfg(feature = "perf-metrics")]
+        size_histogram: crate::metrics::MessageSizeHistogram,
+    }
+
+    impl std::fmt::Debug for NetworkSettings {
+        #[allow(deprecated)]
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            let mut debug = f.debug_struct("NetworkSettings");
+            debug
+                .field("max_message_size", &self.max_message_size)
+                .field("allow_deflate", &self.allow_deflate);
+            #[cfg(feature = "hmac")]
+            debug.field("hmac_key", &self.hmac_key.map(|_| "<redacted>"));
+            debug
+                .field("peers", &self.peers)
+                .field("allowed_kinds", &self.allowed_kinds);
+            #[cfg(feature = "perf-metrics")]
+            debug.field("size_histogram", &self.size_histogram);
+            debug.finish()
+        }
     }
 
     impl Default for NetworkSettings {
+        #[allow(deprecated)]
         fn default() -> Self {
             Self {
                 max_message_size: 64 << 20,
+                allow_deflate: true,
+                #[cfg(feature = "hmac")]
+                hmac_key: None,
+                owner: None,
+                peers: PeerCounter::default(),
+                allowed_kinds: KindFilter::default(),
+                #[cfg(feature = "perf-metrics")]
+                size_histogram: Default::default(),
+            }
+        }
+    }
+
+    impl NetworkSettings {
+        /// Settings without any limit on message size
+        pub fn unlimited() -> Self {
+            Self {
+                max_message_size: usize::MAX,
+                ..Default::default()
+            }
+        }
+
+        /// Settings suited to a typical game, allowing messages of up to 1 MiB
+        pub fn game_default() -> Self {
+            Self {
+                max_message_size: 1 << 20,
+                ..Default::default()
+            }
+        }
+
+        /// The number of live connections using these settings
+        ///
+        /// Clones of the settings share the same count, so this can be read from the
+        /// `NetworkSettings` resource that was handed to `connect`. Settings cloned into another
+        /// app start counting from zero there.
+        pub fn peer_count(&self) -> usize {
+            self.peers.count()
+        }
+
+        /// Whether these settings are the `NetworkSettings` resource of `world`
+        pub(crate) fn is_owned_by(&self, world: WorldId) -> bool {
+            self.owner == Some(world)
+        }
+
+        /// Makes these settings the `NetworkSettings` resource of `world`, giving them their own
+        /// connection state so they no longer share it with the app they were cloned from
+        pub(crate) fn adopt(&mut self, world: WorldId) {
+            self.owner = Some(world);
+            self.peers = PeerCounter::default();
+            #[cfg(feature = "perf-metrics")]
+            {
+                self.size_histogram = Default::default();
             }
         }
+
+        /// Only accepts packets whose kind is one of the given `NetworkMessage::NAME`s
+        ///
+        /// Packets of any other kind are dropped before they reach eventwork. By default every
+        /// kind is accepted.
+        pub fn allow_message_kinds<I, S>(&mut self, kinds: I)
+        where
+            I: IntoIterator<Item = S>,
+            S: Into<String>,
+        {
+            self.allowed_kinds = KindFilter::new(kinds);
+        }
+
+        /// The sizes of the messages sent and received using these settings
+        #[cfg(feature = "perf-metrics")]
+        pub fn size_histogram(&self) -> &crate::metrics::MessageSizeHistogram {
+            &self.size_histogram
+        }
     }
 
     /// A dummy struct as WASM is unable to accept connections and act as a server
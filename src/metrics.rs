@@ -0,0 +1,278 @@
+//! Message size tracking, enabled with the `perf-metrics` feature
+
+use std::{
+    collections::HashMap,
+    fmt::Write,
+    sync::{Arc, Mutex},
+};
+
+use bevy::{
+    diagnostic::{Diagnostic, DiagnosticPath, Diagnostics, RegisterDiagnostic},
+    prelude::*,
+};
+
+use crate::{packet::packet_kind, NetworkSettings};
+
+/// The number of power of two buckets needed to cover every `usize`
+const BUCKETS: usize = usize::BITS as usize + 1;
+
+/// The distribution of encoded message sizes for a single message kind
+///
+/// Sizes are grouped into power of two buckets, so percentiles are reported as the upper bound
+/// of the bucket they fall in.
+#[derive(Clone, Debug)]
+pub struct SizeDistribution {
+    buckets: [u64; BUCKETS],
+    count: u64,
+    max: usize,
+}
+
+impl Default for SizeDistribution {
+    fn default() -> Self {
+        Self {
+            buckets: [0; BUCKETS],
+            count: 0,
+            max: 0,
+        }
+    }
+}
+
+impl SizeDistribution {
+    fn record(&mut self, size: usize) {
+        let bucket = (usize::BITS - size.leading_zeros()) as usize;
+        self.buckets[bucket] += 1;
+        self.count += 1;
+        self.max = self.max.max(size);
+    }
+
+    /// The number of messages recorded
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    /// The largest message recorded, in bytes
+    pub fn max(&self) -> usize {
+        self.max
+    }
+
+    /// The size in bytes that `percentile` percent of messages are at or below
+    pub fn percentile(&self, percentile: f64) -> usize {
+        let target = ((percentile / 100.0) * self.count as f64).ceil().max(1.0) as u64;
+
+        let mut seen = 0;
+        for (bucket, count) in self.buckets.iter().enumerate() {
+            seen += count;
+            if seen >= target {
+                let upper_bound = match bucket {
+                    0 => 0,
+                    bucket => usize::MAX >> (usize::BITS as usize - bucket),
+                };
+                return upper_bound.min(self.max);
+            }
+        }
+
+        self.max
+    }
+}
+
+/// Per message kind histograms of the encoded size of sent and received messages
+///
+/// Every clone shares the same data. The histogram is filled in by the connections using the
+/// [`NetworkSettings`] it belongs to and is inserted as a resource by the `NetworkPlugin`.
+#[derive(Clone, Debug, Default, Resource)]
+pub struct MessageSizeHistogram {
+    sent: Arc<Mutex<HashMap<String, SizeDistribution>>>,
+    received: Arc<Mutex<HashMap<String, SizeDistribution>>>,
+}
+
+impl MessageSizeHistogram {
+    /// The 95th percentile size of all sent messages
+    pub const SENT_P95: DiagnosticPath =
+        DiagnosticPath::const_new("websocket/sent_message_size_p95");
+
+    /// The 95th percentile size of all received messages
+    pub const RECEIVED_P95: DiagnosticPath =
+        DiagnosticPath::const_new("websocket/received_message_size_p95");
+
+    pub(crate) fn record_sent(&self, encoded: &[u8]) {
+        Self::record(&self.sent, encoded);
+    }
+
+    pub(crate) fn record_received(&self, encoded: &[u8]) {
+        Self::record(&self.received, encoded);
+    }
+
+    fn record(histograms: &Mutex<HashMap<String, SizeDistribution>>, encoded: &[u8]) {
+        let kind = packet_kind(encoded).unwrap_or("<unknown>");
+        let mut histograms = histograms.lock().expect("Histogram was poisoned");
+
+        match histograms.get_mut(kind) {
+            Some(distribution) => distribution.record(encoded.len()),
+            None => {
+                let mut distribution = SizeDistribution::default();
+                distribution.record(encoded.len());
+                histograms.insert(kind.to_string(), distribution);
+            }
+        }
+    }
+
+    /// The size distribution of sent messages of the given kind
+    pub fn sent(&self, kind: &str) -> Option<SizeDistribution> {
+        self.sent
+            .lock()
+            .expect("Histogram was poisoned")
+            .get(kind)
+            .cloned()
+    }
+
+    /// The size distribution of received messages of the given kind
+    pub fn received(&self, kind: &str) -> Option<SizeDistribution> {
+        self.received
+            .lock()
+            .expect("Histogram was poisoned")
+            .get(kind)
+            .cloned()
+    }
+
+    /// Formats every histogram as a human readable table
+    pub fn report(&self) -> String {
+        let mut report = format!(
+            "{:<10} {:<40} {:>10} {:>10} {:>10} {:>10} {:>10}\n",
+            "direction", "kind", "count", "p50", "p95", "p99", "max"
+        );
+
+        for (direction, histograms) in [("sent", &self.sent), ("received", &self.received)] {
+            let histograms = histograms.lock().expect("Histogram was poisoned");
+            let mut kinds: Vec<_> = histograms.iter().collect();
+            kinds.sort_by_key(|(kind, _)| *kind);
+
+            for (kind, distribution) in kinds {
+                let _ = writeln!(
+                    report,
+                    "{:<10} {:<40} {:>10} {:>10} {:>10} {:>10} {:>10}",
+                    direction,
+                    kind,
+                    distribution.count(),
+                    distribution.percentile(50.0),
+                    distribution.percentile(95.0),
+                    distribution.percentile(99.0),
+                    distribution.max()
+                );
+            }
+        }
+
+        report
+    }
+
+    fn overall_p95(histograms: &Mutex<HashMap<String, SizeDistribution>>) -> Option<f64> {
+        let histograms = histograms.lock().expect("Histogram was poisoned");
+        let mut overall = SizeDistribution::default();
+        for distribution in histograms.values() {
+            for (bucket, count) in distribution.buckets.iter().enumerate() {
+                overall.buckets[bucket] += count;
+            }
+            overall.count += distribution.count;
+            overall.max = overall.max.max(distribution.max);
+        }
+
+        (overall.count > 0).then(|| overall.percentile(95.0) as f64)
+    }
+}
+
+pub(crate) fn build(app: &mut App) {
+    app.register_diagnostic(Diagnostic::new(MessageSizeHistogram::SENT_P95).with_suffix(" B"))
+        .register_diagnostic(Diagnostic::new(MessageSizeHistogram::RECEIVED_P95).with_suffix(" B"))
+        .add_systems(
+            PostUpdate,
+            (share_histogram, measure_histogram.after(share_histogram)),
+        );
+}
+
+/// Inserts the histogram of the current [`NetworkSettings`] as a resource
+fn share_histogram(settings: Option<Res<NetworkSettings>>, mut commands: Commands) {
+    if let Some(settings) = settings.filter(|settings| settings.is_changed()) {
+        commands.insert_resource(settings.size_histogram().clone());
+    }
+}
+
+fn measure_histogram(histogram: Option<Res<MessageSizeHistogram>>, mut diagnostics: Diagnostics) {
+    let Some(histogram) = histogram else {
+        return;
+    };
+
+    if let Some(p95) = MessageSizeHistogram::overall_p95(&histogram.sent) {
+        diagnostics.add_measurement(&MessageSizeHistogram::SENT_P95, || p95);
+    }
+    if let Some(p95) = MessageSizeHistogram::overall_p95(&histogram.received) {
+        diagnostics.add_measurement(&MessageSizeHistogram::RECEIVED_P95, || p95);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn encoded(kind: &str, size: usize) -> Vec<u8> {
+        bincode::serialize(&(kind, vec![0u8; size])).unwrap()
+    }
+
+    #[test]
+    fn percentiles_are_bucket_upper_bounds() {
+        let mut distribution = SizeDistribution::default();
+        for size in [1, 2, 3, 100] {
+            distribution.record(size);
+        }
+
+        assert_eq!(distribution.count(), 4);
+        assert_eq!(distribution.max(), 100);
+        assert_eq!(distribution.percentile(25.0), 1);
+        assert_eq!(distribution.percentile(50.0), 3);
+        assert_eq!(distribution.percentile(100.0), 100);
+    }
+
+    #[test]
+    fn empty_distribution_reports_zero() {
+        let distribution = SizeDistribution::default();
+        assert_eq!(distribution.percentile(95.0), 0);
+    }
+
+    #[test]
+    fn records_by_kind_and_direction() {
+        let histogram = MessageSizeHistogram::default();
+        histogram.record_sent(&encoded("chat", 10));
+        histogram.record_sent(&encoded("chat", 20));
+        histogram.record_received(&encoded("move", 5));
+
+        assert_eq!(histogram.sent("chat").unwrap().count(), 2);
+        assert!(histogram.sent("move").is_none());
+        assert_eq!(histogram.received("move").unwrap().count(), 1);
+        assert_eq!(
+            histogram.received("move").unwrap().max(),
+            encoded("move", 5).len()
+        );
+    }
+
+    #[test]
+    fn clones_share_data() {
+        let histogram = MessageSizeHistogram::default();
+        histogram.clone().record_sent(&encoded("chat", 1));
+        assert!(histogram.sent("chat").is_some());
+    }
+
+    #[test]
+    fn undecodable_packets_are_recorded_as_unknown() {
+        let histogram = MessageSizeHistogram::default();
+        histogram.record_received(&[0xff]);
+        assert_eq!(histogram.received("<unknown>").unwrap().count(), 1);
+    }
+
+    #[test]
+    fn report_lists_kinds_in_order() {
+        let histogram = MessageSizeHistogram::default();
+        histogram.record_sent(&encoded("b", 1));
+        histogram.record_sent(&encoded("a", 1));
+
+        let report = histogram.report();
+        assert!(report.find(" a ").unwrap() < report.find(" b ").unwrap());
+    }
+}
@@ -0,0 +1,85 @@
+use std::{collections::HashSet, sync::Arc};
+
+/// Reads the message kind from the start of a bincode encoded `NetworkPacket`
+///
+/// The kind is the packet's first field, encoded as a little-endian `u64` length followed by
+/// its UTF-8 bytes, so it can be read without decoding the rest of the packet.
+pub(crate) fn packet_kind(encoded: &[u8]) -> Option<&str> {
+    let length = u64::from_le_bytes(encoded.get(..8)?.try_into().ok()?);
+    let end = 8usize.checked_add(usize::try_from(length).ok()?)?;
+    std::str::from_utf8(encoded.get(8..end)?).ok()
+}
+
+/// An optional whitelist of message kinds accepted from peers
+#[derive(Clone, Debug, Default)]
+pub(crate) struct KindFilter(Option<Arc<HashSet<String>>>);
+
+impl KindFilter {
+    pub(crate) fn new<I, S>(kinds: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        Self(Some(Arc::new(kinds.into_iter().map(Into::into).collect())))
+    }
+
+    /// Whether the encoded packet's kind may be passed on to eventwork
+    pub(crate) fn allows(&self, encoded: &[u8]) -> bool {
+        match &self.0 {
+            Some(kinds) => packet_kind(encoded).is_some_and(|kind| kinds.contains(kind)),
+            None => true,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn encoded(kind: &str, data: &[u8]) -> Vec<u8> {
+        bincode::serialize(&(kind, data)).unwrap()
+    }
+
+    #[test]
+    fn reads_kind() {
+        assert_eq!(packet_kind(&encoded("chat", &[1, 2, 3])), Some("chat"));
+        assert_eq!(packet_kind(&encoded("", &[])), Some(""));
+    }
+
+    #[test]
+    fn rejects_truncated_kind() {
+        let encoded = encoded("chat", &[]);
+        assert_eq!(packet_kind(&encoded[..4]), None);
+        assert_eq!(packet_kind(&encoded[..10]), None);
+    }
+
+    #[test]
+    fn rejects_oversized_length() {
+        let mut encoded = u64::MAX.to_le_bytes().to_vec();
+        encoded.extend_from_slice(b"chat");
+        assert_eq!(packet_kind(&encoded), None);
+    }
+
+    #[test]
+    fn rejects_invalid_utf8() {
+        let mut encoded = 2u64.to_le_bytes().to_vec();
+        encoded.extend_from_slice(&[0xff, 0xfe]);
+        assert_eq!(packet_kind(&encoded), None);
+    }
+
+    #[test]
+    fn default_filter_allows_everything() {
+        let filter = KindFilter::default();
+        assert!(filter.allows(&encoded("chat", &[])));
+        assert!(filter.allows(&[0xff]));
+    }
+
+    #[test]
+    fn filter_allows_only_listed_kinds() {
+        let filter = KindFilter::new(["chat", "move"]);
+        assert!(filter.allows(&encoded("chat", &[])));
+        assert!(filter.allows(&encoded("move", &[1])));
+        assert!(!filter.allows(&encoded("admin", &[])));
+        assert!(!filter.allows(&[0xff]));
+    }
+}
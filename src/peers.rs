@@ -0,0 +1,56 @@
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc,
+};
+#[cfg(not(target_arch = "wasm32"))]
+use std::task::{Context, Poll};
+
+use futures::task::AtomicWaker;
+
+/// A live connection count shared by every clone of a `NetworkSettings`
+#[derive(Clone, Debug, Default)]
+pub(crate) struct PeerCounter {
+    count: Arc<AtomicUsize>,
+    released: Arc<AtomicWaker>,
+}
+
+impl PeerCounter {
+    /// The number of connections currently being tracked
+    pub(crate) fn count(&self) -> usize {
+        self.count.load(Ordering::Acquire)
+    }
+
+    /// Counts a connection until the returned guard is dropped
+    pub(crate) fn track(&self) -> PeerGuard {
+        self.count.fetch_add(1, Ordering::AcqRel);
+        PeerGuard(self.clone())
+    }
+
+    /// Resolves once fewer than `limit` connections are tracked
+    ///
+    /// Only the task that polled most recently is woken when a connection drops.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub(crate) fn poll_below(&self, limit: usize, cx: &mut Context<'_>) -> Poll<()> {
+        if self.count() < limit {
+            return Poll::Ready(());
+        }
+
+        self.released.register(cx.waker());
+        // A connection may have dropped before the waker was registered
+        if self.count() < limit {
+            Poll::Ready(())
+        } else {
+            Poll::Pending
+        }
+    }
+}
+
+/// Keeps a connection counted in its [`PeerCounter`] while alive
+pub(crate) struct PeerGuard(PeerCounter);
+
+impl Drop for PeerGuard {
+    fn drop(&mut self) {
+        self.0.count.fetch_sub(1, Ordering::AcqRel);
+        self.0.released.wake();
+    }
+}
@@ -0,0 +1,346 @@
+use std::time::Duration;
+
+use bevy::{
+    app::PluginGroupBuilder,
+    ecs::world::WorldId,
+    prelude::*,
+    tasks::{TaskPool, TaskPoolBuilder},
+};
+use bevy_eventwork::{EventworkPlugin, EventworkRuntime};
+
+use crate::{NetworkSettings, WebSocketProvider};
+
+/// Sets up a websocket client: eventwork, its runtime, [`NetworkSettings`] and [`NetworkPlugin`]
+///
+/// An existing `EventworkPlugin` or `EventworkRuntime<TaskPool>` is kept, otherwise a two
+/// thread pool with threads named `network` is created.
+#[derive(Clone, Debug, Default)]
+pub struct WebSocketClientPlugin {
+    /// The settings inserted as the [`NetworkSettings`] resource
+    pub settings: NetworkSettings,
+}
+
+impl Plugin for WebSocketClientPlugin {
+    fn build(&self, app: &mut App) {
+        add_websocket_plugins(app, &self.settings, DEFAULT_THREADS);
+    }
+}
+
+/// Sets up a websocket server: eventwork, its runtime, [`NetworkSettings`] and [`NetworkPlugin`]
+///
+/// An existing `EventworkPlugin` or `EventworkRuntime<TaskPool>` is kept, otherwise a two
+/// thread pool with threads named `network` is created. Not available on WASM, which cannot
+/// listen for connections.
+#[cfg(not(target_arch = "wasm32"))]
+#[derive(Clone, Debug, Default)]
+pub struct WebSocketServerPlugin {
+    /// The settings inserted as the [`NetworkSettings`] resource
+    pub settings: NetworkSettings,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl Plugin for WebSocketServerPlugin {
+    fn build(&self, app: &mut App) {
+        add_websocket_plugins(app, &self.settings, DEFAULT_THREADS);
+    }
+}
+
+/// Sets up websockets like [`WebSocketClientPlugin`] and [`WebSocketServerPlugin`], with a
+/// configurable number of runtime threads
+///
+/// An existing `EventworkPlugin` or `EventworkRuntime<TaskPool>` is kept, in which case
+/// `threads` is ignored.
+#[derive(Clone, Debug)]
+pub struct WebSocketPlugin {
+    /// The settings inserted as the [`NetworkSettings`] resource
+    pub settings: NetworkSettings,
+    /// The number of threads of the `EventworkRuntime` task pool
+    pub threads: usize,
+}
+
+impl Default for WebSocketPlugin {
+    fn default() -> Self {
+        Self {
+            settings: NetworkSettings::default(),
+            threads: DEFAULT_THREADS,
+        }
+    }
+}
+
+impl Plugin for WebSocketPlugin {
+    fn build(&self, app: &mut App) {
+        add_websocket_plugins(app, &self.settings, self.threads);
+    }
+}
+
+/// [`NetworkPlugin`] and [`WebSocketPlugin`], configurable through `PluginGroup::set`
+#[derive(Clone, Debug, Default)]
+pub struct WebSocketPluginGroup;
+
+impl PluginGroup for WebSocketPluginGroup {
+    fn build(self) -> PluginGroupBuilder {
+        PluginGroupBuilder::start::<Self>()
+            .add(NetworkPlugin::default())
+            .add(WebSocketPlugin::default())
+    }
+}
+
+/// The number of `EventworkRuntime` threads created when no runtime exists yet
+const DEFAULT_THREADS: usize = 2;
+
+fn add_websocket_plugins(app: &mut App, settings: &NetworkSettings, threads: usize) {
+    if !app.is_plugin_added::<EventworkPlugin<WebSocketProvider, TaskPool>>() {
+        app.add_plugins(EventworkPlugin::<WebSocketProvider, TaskPool>::default());
+    }
+
+    if !app
+        .world()
+        .contains_resource::<EventworkRuntime<TaskPool>>()
+    {
+        app.insert_resource(EventworkRuntime(
+            TaskPoolBuilder::new()
+                .num_threads(threads)
+                .thread_name("network".to_string())
+                .build(),
+        ));
+    }
+
+    app.insert_resource(settings.clone());
+
+    if !app.is_plugin_added::<NetworkPlugin>() {
+        app.add_plugins(NetworkPlugin::default());
+    }
+}
+
+/// Adds the systems backing the websocket provider's own features
+///
+/// This is added alongside `bevy_eventwork::EventworkPlugin` and does not replace it.
+///
+/// When the app exits every connection's send queue is drained and the connection is closed,
+/// waiting at most `NetworkSettings::shutdown_timeout` on native targets.
+///
+/// It also configures [`WebSocketSet::Receive`] in `PreUpdate` and [`WebSocketSet::Send`] in
+/// `PostUpdate`. Systems reading messages placed in `Receive` run before every system in `Update`,
+/// and systems sending messages placed in `Send` run after them, so game logic always sees this
+/// frame's messages and its replies leave in the same frame.
+#[derive(Clone, Debug, Default)]
+pub struct NetworkPlugin {
+    graceful_shutdown: Option<Duration>,
+    abandon_connections_on_exit: bool,
+}
+
+/// System sets ordering network message handling around game logic
+///
+/// See [`NetworkPlugin`] for the schedules they run in.
+#[derive(SystemSet, Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum WebSocketSet {
+    /// Systems reading received messages, run in `PreUpdate`
+    Receive,
+    /// Systems sending messages, run in `PostUpdate`
+    Send,
+}
+
+impl NetworkPlugin {
+    /// Waits at most `timeout` for connections to close when the app exits, instead of
+    /// `NetworkSettings::shutdown_timeout`
+    ///
+    /// On WASM this does nothing as the browser closes the connection itself.
+    pub fn with_graceful_shutdown(mut self, timeout: Duration) -> Self {
+        self.graceful_shutdown = Some(timeout);
+        self
+    }
+
+    /// Leaves connections open when the app exits instead of closing them
+    pub fn without_graceful_shutdown(mut self) -> Self {
+        self.abandon_connections_on_exit = true;
+        self
+    }
+}
+
+impl Plugin for NetworkPlugin {
+    fn build(&self, app: &mut App) {
+        app.configure_sets(PreUpdate, WebSocketSet::Receive)
+            .configure_sets(PostUpdate, WebSocketSet::Send)
+            .init_resource::<ConnectedPeerCount>()
+            .add_systems(PreStartup, adopt_network_settings)
+            .add_systems(First, adopt_network_settings)
+            .add_systems(
+                PreUpdate,
+                count_connected_peers.in_set(WebSocketSet::Receive),
+            );
+
+        #[cfg(not(target_arch = "wasm32"))]
+        app.add_systems(
+            PreUpdate,
+            (share_bound_address, share_upgrade_response).in_set(WebSocketSet::Receive),
+        );
+
+        #[cfg(not(target_arch = "wasm32"))]
+        if !self.abandon_connections_on_exit {
+            app.insert_resource(GracefulShutdown(self.graceful_shutdown));
+            app.add_systems(PostUpdate, shutdown_on_exit.after(WebSocketSet::Send));
+        }
+
+        #[cfg(target_arch = "wasm32")]
+        let _ = (self.graceful_shutdown, self.abandon_connections_on_exit);
+
+        #[cfg(feature = "perf-metrics")]
+        crate::metrics::build(app);
+    }
+}
+
+/// Gives `NetworkSettings` cloned from another app their own connection state
+///
+/// This runs before anything can listen or connect with the settings, both at startup and
+/// every frame in case the resource was replaced.
+fn adopt_network_settings(settings: Option<ResMut<NetworkSettings>>, world: WorldId) {
+    // Filtered before mutating so the resource is only marked as changed when it is adopted
+    if let Some(mut settings) = settings.filter(|settings| !settings.is_owned_by(world)) {
+        settings.adopt(world);
+    }
+}
+
+/// The number of connected peers, copied every frame from
+/// [`NetworkSettings::peer_count`] by [`NetworkPlugin`]
+///
+/// It only changes when a peer connects or disconnects, so it works with
+/// `run_if(resource_changed::<ConnectedPeerCount>)`.
+#[derive(Resource, Clone, Copy, Debug, Default, PartialEq, Eq, Deref)]
+pub struct ConnectedPeerCount(pub usize);
+
+fn count_connected_peers(
+    settings: Option<Res<NetworkSettings>>,
+    mut peer_count: ResMut<ConnectedPeerCount>,
+) {
+    let count = settings.map_or(0, |settings| settings.peer_count());
+    peer_count.set_if_neq(ConnectedPeerCount(count));
+}
+
+/// The address the server is listening on, inserted by [`NetworkPlugin`] once it is bound
+///
+/// Listening on port 0 lets the OS pick a free port, which can then be read from here.
+#[cfg(not(target_arch = "wasm32"))]
+#[derive(Resource, Clone, Copy, Debug, PartialEq, Eq, Deref)]
+pub struct BoundAddress(pub std::net::SocketAddr);
+
+#[cfg(not(target_arch = "wasm32"))]
+fn share_bound_address(
+    settings: Option<Res<NetworkSettings>>,
+    bound_address: Option<Res<BoundAddress>>,
+    mut commands: Commands,
+) {
+    let Some(addr) = settings.and_then(|settings| settings.bound_addr()) else {
+        return;
+    };
+
+    if bound_address.map(|bound_address| bound_address.0) != Some(addr) {
+        commands.insert_resource(BoundAddress(addr));
+    }
+}
+
+/// The HTTP response to the client's last WebSocket handshake, inserted by [`NetworkPlugin`]
+/// once it connects
+///
+/// Useful for reading headers set by the server, such as its version or a session cookie.
+#[cfg(not(target_arch = "wasm32"))]
+#[derive(Resource, Clone, Debug, PartialEq, Eq)]
+pub struct UpgradeResponse {
+    /// The HTTP status code, `101 Switching Protocols` for a successful upgrade
+    pub status: u16,
+    /// The response headers in the order they were received
+    pub headers: Vec<(String, String)>,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl UpgradeResponse {
+    /// The value of the first header named `name`, compared case insensitively
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(header, _)| header.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn share_upgrade_response(settings: Option<Res<NetworkSettings>>, mut commands: Commands) {
+    if let Some(response) = settings.and_then(|settings| settings.take_upgrade_response()) {
+        commands.insert_resource(response);
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+#[derive(Resource)]
+struct GracefulShutdown(Option<Duration>);
+
+#[cfg(not(target_arch = "wasm32"))]
+fn shutdown_on_exit(
+    mut exits: EventReader<AppExit>,
+    timeout: Res<GracefulShutdown>,
+    settings: Option<Res<NetworkSettings>>,
+) {
+    if exits.read().last().is_none() {
+        return;
+    }
+
+    let Some(settings) = settings else {
+        return;
+    };
+
+    info!("Closing all connections before exiting");
+    if !settings.shutdown(timeout.0.unwrap_or(*settings.shutdown_timeout)) {
+        warn!("Timed out waiting for connections to close");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy_eventwork::Network;
+
+    use super::*;
+
+    fn assert_websockets_set_up(app: &mut App) {
+        app.update();
+        assert!(app
+            .world()
+            .contains_resource::<Network<WebSocketProvider>>());
+        assert!(app
+            .world()
+            .contains_resource::<EventworkRuntime<TaskPool>>());
+        assert!(app.world().contains_resource::<NetworkSettings>());
+        assert!(app.world().contains_resource::<ConnectedPeerCount>());
+    }
+
+    #[test]
+    fn client_plugin_sets_up_websockets() {
+        let mut app = App::new();
+        app.add_plugins(WebSocketClientPlugin::default());
+        assert_websockets_set_up(&mut app);
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    #[test]
+    fn server_plugin_sets_up_websockets() {
+        let mut app = App::new();
+        app.add_plugins(WebSocketServerPlugin::default());
+        assert_websockets_set_up(&mut app);
+    }
+
+    #[test]
+    fn plugin_group_sets_up_websockets() {
+        let mut app = App::new();
+        app.add_plugins(WebSocketPluginGroup);
+        assert_websockets_set_up(&mut app);
+    }
+
+    #[test]
+    fn keeps_an_existing_eventwork_plugin() {
+        let mut app = App::new();
+        app.add_plugins(EventworkPlugin::<WebSocketProvider, TaskPool>::default())
+            .add_plugins(WebSocketPlugin {
+                threads: 1,
+                ..Default::default()
+            });
+        assert_websockets_set_up(&mut app);
+    }
+}
@@ -0,0 +1,76 @@
+use std::{sync::Arc, time::Duration};
+
+use async_channel::{Receiver, Sender};
+use bevy::prelude::{Deref, DerefMut};
+
+use crate::peers::{PeerCounter, PeerGuard};
+
+/// How long the `NetworkPlugin` waits for connections to close when the app exits
+///
+/// Connections still open after this are dropped without finishing their close handshake.
+/// Defaults to one second.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deref, DerefMut)]
+pub struct ShutdownTimeout(pub Duration);
+
+impl Default for ShutdownTimeout {
+    fn default() -> Self {
+        Self(Duration::from_secs(1))
+    }
+}
+
+/// Tells every send loop sharing a `NetworkSettings` to drain and close its connection
+#[derive(Clone, Debug)]
+pub(crate) struct ShutdownSignal {
+    trigger: Sender<()>,
+    /// Behind an `Arc` as the receiver is `!Unpin`, which would make every stream holding the
+    /// settings `!Unpin` too
+    listener: Arc<Receiver<()>>,
+    senders: PeerCounter,
+}
+
+impl Default for ShutdownSignal {
+    fn default() -> Self {
+        let (trigger, listener) = async_channel::bounded(1);
+        Self {
+            trigger,
+            listener: Arc::new(listener),
+            senders: PeerCounter::default(),
+        }
+    }
+}
+
+impl ShutdownSignal {
+    /// Starts the shutdown. Calling this more than once has no further effect
+    pub(crate) fn trigger(&self) {
+        self.trigger.close();
+    }
+
+    /// Whether [`ShutdownSignal::trigger`] has been called
+    pub(crate) fn is_triggered(&self) -> bool {
+        self.trigger.is_closed()
+    }
+
+    /// Resolves once the shutdown has been triggered
+    pub(crate) async fn triggered(&self) {
+        // Nothing is ever sent on the channel, so this only returns when it is closed
+        let _ = self.listener.recv().await;
+    }
+
+    /// Marks a send loop as running until the returned guard is dropped
+    pub(crate) fn track_sender(&self) -> PeerGuard {
+        self.senders.track()
+    }
+
+    /// Waits until every tracked send loop has finished, or `timeout` elapses
+    ///
+    /// Returns `true` if all send loops finished in time
+    pub(crate) async fn drained(&self, timeout: Duration) -> bool {
+        async_std::future::timeout(timeout, async {
+            while self.senders.count() > 0 {
+                async_std::task::sleep(Duration::from_millis(5)).await;
+            }
+        })
+        .await
+        .is_ok()
+    }
+}
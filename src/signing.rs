@@ -0,0 +1,82 @@
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// The size of the HMAC-SHA256 tag appended to every signed packet
+pub(crate) const TAG_SIZE: usize = 32;
+
+/// Appends the HMAC-SHA256 tag of `payload` to it
+pub(crate) fn sign(key: &[u8; 32], payload: &mut Vec<u8>) {
+    let mut mac = HmacSha256::new_from_slice(key).expect("HMAC accepts keys of any size");
+    mac.update(payload);
+    payload.extend_from_slice(&mac.finalize().into_bytes());
+}
+
+/// Checks and strips the HMAC-SHA256 tag at the end of `signed`
+///
+/// Returns `false` without modifying `signed` if the tag is missing or does not match.
+pub(crate) fn verify(key: &[u8; 32], signed: &mut Vec<u8>) -> bool {
+    let Some(payload_size) = signed.len().checked_sub(TAG_SIZE) else {
+        return false;
+    };
+
+    let (payload, tag) = signed.split_at(payload_size);
+    let mut mac = HmacSha256::new_from_slice(key).expect("HMAC accepts keys of any size");
+    mac.update(payload);
+    if mac.verify_slice(tag).is_err() {
+        return false;
+    }
+
+    signed.truncate(payload_size);
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEY: [u8; 32] = [7; 32];
+
+    #[test]
+    fn verifies_and_strips_signed_payload() {
+        let mut signed = b"payload".to_vec();
+        sign(&KEY, &mut signed);
+        assert_eq!(signed.len(), b"payload".len() + TAG_SIZE);
+
+        assert!(verify(&KEY, &mut signed));
+        assert_eq!(signed, b"payload");
+    }
+
+    #[test]
+    fn rejects_tampered_tag() {
+        let mut signed = b"payload".to_vec();
+        sign(&KEY, &mut signed);
+        *signed.last_mut().unwrap() ^= 1;
+
+        let tampered = signed.clone();
+        assert!(!verify(&KEY, &mut signed));
+        assert_eq!(signed, tampered);
+    }
+
+    #[test]
+    fn rejects_tampered_payload() {
+        let mut signed = b"payload".to_vec();
+        sign(&KEY, &mut signed);
+        signed[0] ^= 1;
+        assert!(!verify(&KEY, &mut signed));
+    }
+
+    #[test]
+    fn rejects_other_key() {
+        let mut signed = b"payload".to_vec();
+        sign(&KEY, &mut signed);
+        assert!(!verify(&[8; 32], &mut signed));
+    }
+
+    #[test]
+    fn rejects_missing_tag() {
+        let mut signed = vec![0; TAG_SIZE - 1];
+        assert!(!verify(&KEY, &mut signed));
+    }
+}
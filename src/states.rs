@@ -0,0 +1,195 @@
+//! Drives Bevy [`States`] from connection events, enabled with the `states` feature
+
+use std::collections::HashMap;
+
+use bevy::{prelude::*, state::state::FreelyMutableState};
+use bevy_eventwork::NetworkEvent;
+
+/// A change in connection status that can trigger a state transition
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ConnectionChange {
+    /// A connection was established
+    Connected,
+    /// A connection was lost
+    Disconnected,
+}
+
+/// Which state to move to when the connection status changes
+///
+/// Transitions registered for a specific current state take priority over the fallbacks set
+/// with [`StateTransitionMap::on_connected`] and [`StateTransitionMap::on_disconnected`].
+#[derive(Resource, Clone, Debug)]
+pub struct StateTransitionMap<S: States> {
+    transitions: HashMap<(S, ConnectionChange), S>,
+    on_connected: Option<S>,
+    on_disconnected: Option<S>,
+}
+
+impl<S: States> Default for StateTransitionMap<S> {
+    fn default() -> Self {
+        Self {
+            transitions: HashMap::default(),
+            on_connected: None,
+            on_disconnected: None,
+        }
+    }
+}
+
+impl<S: States> StateTransitionMap<S> {
+    /// Moves to `state` whenever a connection is established
+    pub fn on_connected(mut self, state: S) -> Self {
+        self.on_connected = Some(state);
+        self
+    }
+
+    /// Moves to `state` whenever a connection is lost
+    pub fn on_disconnected(mut self, state: S) -> Self {
+        self.on_disconnected = Some(state);
+        self
+    }
+
+    /// Moves from `from` to `to` when `change` happens while in `from`
+    pub fn with_transition(mut self, from: S, change: ConnectionChange, to: S) -> Self {
+        self.transitions.insert((from, change), to);
+        self
+    }
+
+    /// The state to move to from `current` after `change`, if any
+    pub fn next_state(&self, current: &S, change: ConnectionChange) -> Option<&S> {
+        self.transitions
+            .get(&(current.clone(), change))
+            .or(match change {
+                ConnectionChange::Connected => self.on_connected.as_ref(),
+                ConnectionChange::Disconnected => self.on_disconnected.as_ref(),
+            })
+    }
+}
+
+/// Transitions `S` according to a [`StateTransitionMap`] on `NetworkEvent::Connected` and
+/// `NetworkEvent::Disconnected`
+///
+/// `S` must already be initialized on the app, for example with `App::init_state`. The
+/// transitions are read in [`crate::WebSocketSet::Receive`] and applied before `Update`, so game
+/// logic sees the new state in the same frame as the connection event.
+pub struct NetworkStatePlugin<S: FreelyMutableState> {
+    transitions: StateTransitionMap<S>,
+}
+
+impl<S: FreelyMutableState> NetworkStatePlugin<S> {
+    /// Creates the plugin from a transition map
+    pub fn new(transitions: StateTransitionMap<S>) -> Self {
+        Self { transitions }
+    }
+}
+
+impl<S: FreelyMutableState> Plugin for NetworkStatePlugin<S> {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(self.transitions.clone()).add_systems(
+            PreUpdate,
+            transition_on_network_events::<S>.in_set(crate::WebSocketSet::Receive),
+        );
+    }
+}
+
+fn transition_on_network_events<S: FreelyMutableState>(
+    mut network_events: EventReader<NetworkEvent>,
+    transitions: Res<StateTransitionMap<S>>,
+    state: Res<State<S>>,
+    mut next_state: ResMut<NextState<S>>,
+) {
+    for event in network_events.read() {
+        let change = match event {
+            NetworkEvent::Connected(_) => ConnectionChange::Connected,
+            NetworkEvent::Disconnected(_) => ConnectionChange::Disconnected,
+            NetworkEvent::Error(_) => continue,
+        };
+
+        if let Some(next) = transitions.next_state(state.get(), change) {
+            next_state.set(next.clone());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::state::app::StatesPlugin;
+    use bevy_eventwork::ConnectionId;
+
+    use super::*;
+
+    #[derive(States, Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+    enum Screen {
+        #[default]
+        Menu,
+        Lobby,
+        Game,
+    }
+
+    fn app(transitions: StateTransitionMap<Screen>) -> App {
+        let mut app = App::new();
+        app.add_plugins(StatesPlugin)
+            .add_event::<NetworkEvent>()
+            .init_state::<Screen>()
+            .add_plugins(NetworkStatePlugin::new(transitions));
+        app.update();
+        app
+    }
+
+    fn send(app: &mut App, event: NetworkEvent) -> Screen {
+        app.world_mut().send_event(event);
+        app.update();
+        *app.world().resource::<State<Screen>>().get()
+    }
+
+    const PEER: ConnectionId = ConnectionId { id: 1 };
+
+    #[test]
+    fn follows_connection_events() {
+        let mut app = app(StateTransitionMap::default()
+            .on_connected(Screen::Game)
+            .on_disconnected(Screen::Menu));
+
+        assert_eq!(send(&mut app, NetworkEvent::Connected(PEER)), Screen::Game);
+        assert_eq!(
+            send(&mut app, NetworkEvent::Disconnected(PEER)),
+            Screen::Menu
+        );
+    }
+
+    #[test]
+    fn prefers_transitions_from_the_current_state() {
+        let mut app = app(StateTransitionMap::default()
+            .on_connected(Screen::Game)
+            .with_transition(Screen::Menu, ConnectionChange::Connected, Screen::Lobby));
+
+        assert_eq!(send(&mut app, NetworkEvent::Connected(PEER)), Screen::Lobby);
+        assert_eq!(send(&mut app, NetworkEvent::Connected(PEER)), Screen::Game);
+    }
+
+    #[test]
+    fn ignores_changes_without_a_transition() {
+        let mut app = app(StateTransitionMap::default().on_connected(Screen::Game));
+
+        assert_eq!(
+            send(&mut app, NetworkEvent::Disconnected(PEER)),
+            Screen::Menu
+        );
+    }
+
+    #[test]
+    fn applies_the_state_before_update() {
+        #[derive(Resource, Default)]
+        struct SeenInUpdate(Option<Screen>);
+
+        let mut app = app(StateTransitionMap::default().on_connected(Screen::Game));
+        app.init_resource::<SeenInUpdate>().add_systems(
+            Update,
+            |state: Res<State<Screen>>, mut seen: ResMut<SeenInUpdate>| {
+                seen.0 = Some(*state.get());
+            },
+        );
+
+        send(&mut app, NetworkEvent::Connected(PEER));
+        assert_eq!(app.world().resource::<SeenInUpdate>().0, Some(Screen::Game));
+    }
+}
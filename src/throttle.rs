@@ -0,0 +1,193 @@
+use std::time::Duration;
+
+use bevy::{log::warn, utils::Instant};
+
+/// How many errors a single connection may log per second before they are suppressed
+pub(crate) const MAX_ERROR_LOGS_PER_SECOND: u32 = 10;
+
+/// Limits how many errors a connection logs so a misbehaving peer cannot flood the log
+#[derive(Debug)]
+pub(crate) struct LogThrottle {
+    /// Names the connection in the summary of suppressed errors
+    connection: String,
+    max_per_second: u32,
+    last_reset: Instant,
+    count: u32,
+}
+
+impl LogThrottle {
+    pub(crate) fn new(connection: impl Into<String>) -> Self {
+        Self {
+            connection: connection.into(),
+            max_per_second: MAX_ERROR_LOGS_PER_SECOND,
+            last_reset: Instant::now(),
+            count: 0,
+        }
+    }
+
+    /// Records an error, returning whether it should be logged
+    ///
+    /// Errors suppressed during the previous second are summarized once the next second starts,
+    /// or when the throttle is dropped.
+    pub(crate) fn allow(&mut self) -> bool {
+        if self.last_reset.elapsed() >= Duration::from_secs(1) {
+            self.flush();
+        }
+
+        self.count = self.count.saturating_add(1);
+        self.count <= self.max_per_second
+    }
+
+    /// The number of errors suppressed in the current second
+    pub(crate) fn suppressed(&self) -> u32 {
+        self.count.saturating_sub(self.max_per_second)
+    }
+
+    /// Logs how many errors were suppressed and starts a new second
+    fn flush(&mut self) {
+        if self.suppressed() > 0 {
+            warn!(
+                "Suppressed {} duplicate errors from {}",
+                self.suppressed(),
+                self.connection
+            );
+        }
+        self.last_reset = Instant::now();
+        self.count = 0;
+    }
+}
+
+impl Drop for LogThrottle {
+    fn drop(&mut self) {
+        self.flush();
+    }
+}
+
+/// Detects a connection producing more than `limit` errors within `window`
+///
+/// The rate is estimated from the current and previous window, weighting the previous one by
+/// how much of it still overlaps the sliding window.
+#[cfg(not(target_arch = "wasm32"))]
+#[derive(Debug)]
+pub(crate) struct ErrorRateLimit {
+    limit: usize,
+    window: Duration,
+    window_start: Instant,
+    current: usize,
+    previous: usize,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl ErrorRateLimit {
+    pub(crate) fn new(limit: usize, window: Duration) -> Self {
+        Self {
+            limit,
+            window,
+            window_start: Instant::now(),
+            current: 0,
+            previous: 0,
+        }
+    }
+
+    /// Records an error, returning whether the limit has been exceeded
+    pub(crate) fn exceeded(&mut self) -> bool {
+        let elapsed = self.window_start.elapsed();
+        if elapsed >= self.window {
+            let windows = elapsed.as_nanos() / self.window.as_nanos();
+            self.previous = if windows == 1 { self.current } else { 0 };
+            self.current = 0;
+            // Windows stay aligned to the first one, so the overlap with the previous window
+            // is not lost when the first error of a window arrives late
+            let into_window = elapsed.as_nanos() % self.window.as_nanos();
+            self.window_start += elapsed - Duration::from_nanos(into_window as u64);
+        }
+
+        self.current = self.current.saturating_add(1);
+        let overlap =
+            1.0 - (self.window_start.elapsed().as_secs_f64() / self.window.as_secs_f64()).min(1.0);
+        self.current as f64 + self.previous as f64 * overlap > self.limit as f64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn log_throttle_suppresses_errors_past_the_limit() {
+        let mut throttle = LogThrottle::new("peer");
+        for _ in 0..MAX_ERROR_LOGS_PER_SECOND {
+            assert!(throttle.allow());
+        }
+        assert!(!throttle.allow());
+        assert!(!throttle.allow());
+        assert_eq!(throttle.suppressed(), 2);
+    }
+
+    #[test]
+    fn log_throttle_resets_after_a_second() {
+        let mut throttle = LogThrottle::new("peer");
+        for _ in 0..=MAX_ERROR_LOGS_PER_SECOND {
+            throttle.allow();
+        }
+        assert_eq!(throttle.suppressed(), 1);
+
+        throttle.last_reset -= Duration::from_secs(1);
+        assert!(throttle.allow());
+        assert_eq!(throttle.suppressed(), 0);
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    #[test]
+    fn error_rate_limit_trips_past_the_limit() {
+        let mut limit = ErrorRateLimit::new(3, Duration::from_secs(60));
+        assert!(!limit.exceeded());
+        assert!(!limit.exceeded());
+        assert!(!limit.exceeded());
+        assert!(limit.exceeded());
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    #[test]
+    fn error_rate_limit_weights_the_previous_window() {
+        let window = Duration::from_secs(60);
+        let mut limit = ErrorRateLimit::new(3, window);
+        for _ in 0..3 {
+            limit.exceeded();
+        }
+
+        // Just after the window rolls over, the previous window still counts almost fully
+        limit.window_start -= window;
+        assert!(limit.exceeded());
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    #[test]
+    fn error_rate_limit_forgets_old_windows() {
+        let window = Duration::from_secs(60);
+        let mut limit = ErrorRateLimit::new(3, window);
+        for _ in 0..3 {
+            limit.exceeded();
+        }
+
+        limit.window_start -= window * 2;
+        assert!(!limit.exceeded());
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    #[test]
+    fn error_rate_limit_advances_by_whole_windows() {
+        let window = Duration::from_secs(60);
+        let mut limit = ErrorRateLimit::new(3, window);
+        for _ in 0..3 {
+            limit.exceeded();
+        }
+
+        // Halfway into the next window, half of the previous one still counts
+        limit.window_start -= window + window / 2;
+        assert!(!limit.exceeded());
+        assert!(limit.window_start.elapsed() >= window / 2);
+        assert!(limit.window_start.elapsed() < window);
+        assert!(limit.exceeded());
+    }
+}
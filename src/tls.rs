@@ -0,0 +1,185 @@
+//! TLS for native servers, enabled with the `tls` feature
+
+use std::{
+    fs::File,
+    io::{self, BufReader},
+    path::Path,
+    sync::Arc,
+};
+
+use async_std::net::TcpStream;
+use futures_rustls::{
+    rustls::{
+        pki_types::{CertificateDer, PrivateKeyDer},
+        server::WebPkiClientVerifier,
+        RootCertStore, ServerConfig,
+    },
+    TlsAcceptor,
+};
+
+pub use crate::transport::MaybeTlsStream;
+
+/// Builds a server configuration from a PEM encoded certificate chain and private key
+pub fn load_server_config(
+    cert_path: impl AsRef<Path>,
+    key_path: impl AsRef<Path>,
+) -> Result<Arc<ServerConfig>, String> {
+    let (certs, key) = load_cert_and_key(cert_path.as_ref(), key_path.as_ref())?;
+
+    ServerConfig::builder()
+        .with_no_client_auth()
+        .with_single_cert(certs, key)
+        .map(Arc::new)
+        .map_err(|err| format!("Invalid certificate or key: {}", err))
+}
+
+/// Like [`load_server_config`], but also requires clients to present a certificate signed by
+/// one of `client_roots`
+///
+/// Clients without a valid certificate fail the TLS handshake and are never handed to
+/// eventwork.
+pub fn load_server_config_with_client_auth(
+    cert_path: impl AsRef<Path>,
+    key_path: impl AsRef<Path>,
+    client_roots: RootCertStore,
+) -> Result<Arc<ServerConfig>, String> {
+    let (certs, key) = load_cert_and_key(cert_path.as_ref(), key_path.as_ref())?;
+    let verifier = WebPkiClientVerifier::builder(Arc::new(client_roots))
+        .build()
+        .map_err(|err| format!("Invalid client roots: {}", err))?;
+
+    ServerConfig::builder()
+        .with_client_cert_verifier(verifier)
+        .with_single_cert(certs, key)
+        .map(Arc::new)
+        .map_err(|err| format!("Invalid certificate or key: {}", err))
+}
+
+fn load_cert_and_key(
+    cert_path: &Path,
+    key_path: &Path,
+) -> Result<(Vec<CertificateDer<'static>>, PrivateKeyDer<'static>), String> {
+    let mut certs = BufReader::new(
+        File::open(cert_path)
+            .map_err(|err| format!("Failed to open {}: {}", cert_path.display(), err))?,
+    );
+    let certs = rustls_pemfile::certs(&mut certs)
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|err| format!("Failed to read {}: {}", cert_path.display(), err))?;
+
+    let mut key = BufReader::new(
+        File::open(key_path)
+            .map_err(|err| format!("Failed to open {}: {}", key_path.display(), err))?,
+    );
+    let key = rustls_pemfile::private_key(&mut key)
+        .map_err(|err| format!("Failed to read {}: {}", key_path.display(), err))?
+        .ok_or_else(|| format!("No private key found in {}", key_path.display()))?;
+
+    Ok((certs, key))
+}
+
+/// Reads the common name from the subject of a DER encoded X.509 certificate
+///
+/// Only the fields leading up to the subject are walked, the certificate is not validated.
+pub(crate) fn common_name(certificate: &[u8]) -> Option<String> {
+    const VERSION_TAG: u8 = 0xa0;
+    const COMMON_NAME_OID: &[u8] = &[0x55, 0x04, 0x03];
+
+    let (_, certificate, _) = der_element(certificate)?;
+    let (_, tbs_certificate, _) = der_element(certificate)?;
+    let (tag, _, mut rest) = der_element(tbs_certificate)?;
+    // The version is optional and comes before the serial number
+    if tag == VERSION_TAG {
+        (_, _, rest) = der_element(rest)?;
+    }
+    // Skips the signature algorithm, issuer and validity
+    for _ in 0..3 {
+        (_, _, rest) = der_element(rest)?;
+    }
+
+    let (_, mut names, _) = der_element(rest)?;
+    while !names.is_empty() {
+        let (_, mut attributes, next) = der_element(names)?;
+        names = next;
+        while !attributes.is_empty() {
+            let (_, attribute, next) = der_element(attributes)?;
+            attributes = next;
+            let (_, oid, value) = der_element(attribute)?;
+            if oid == COMMON_NAME_OID {
+                let (_, value, _) = der_element(value)?;
+                return String::from_utf8(value.to_vec()).ok();
+            }
+        }
+    }
+    None
+}
+
+/// Splits the first DER element off `input`, returning its tag, its contents and the rest
+fn der_element(input: &[u8]) -> Option<(u8, &[u8], &[u8])> {
+    let (&tag, rest) = input.split_first()?;
+    let (&length, rest) = rest.split_first()?;
+    let (length, rest) = if length < 0x80 {
+        (usize::from(length), rest)
+    } else {
+        // Long form, the low bits give the number of length bytes that follow
+        let octets = usize::from(length & 0x7f);
+        if octets == 0 || octets > std::mem::size_of::<usize>() {
+            return None;
+        }
+        let (length, rest) = rest.split_at_checked(octets)?;
+        let length = length
+            .iter()
+            .fold(0, |length, &byte| length << 8 | usize::from(byte));
+        (length, rest)
+    };
+    let (contents, rest) = rest.split_at_checked(length)?;
+    Some((tag, contents, rest))
+}
+
+/// Performs the TLS handshake on an accepted stream if the server has a TLS configuration
+pub(crate) async fn accept(
+    stream: TcpStream,
+    config: Option<&Arc<ServerConfig>>,
+) -> io::Result<MaybeTlsStream> {
+    match config {
+        Some(config) => {
+            let stream = TlsAcceptor::from(config.clone()).accept(stream).await?;
+            Ok(MaybeTlsStream::Tls(Box::new(stream)))
+        }
+        None => Ok(MaybeTlsStream::Plain(stream)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn certificate(name: &str) -> CertificateDer<'static> {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("tests/fixtures/tls")
+            .join(name);
+        let mut pem = BufReader::new(File::open(path).unwrap());
+        let certificate = rustls_pemfile::certs(&mut pem).next().unwrap().unwrap();
+        certificate
+    }
+
+    #[test]
+    fn reads_common_names() {
+        assert_eq!(
+            common_name(&certificate("client.pem")).as_deref(),
+            Some("player-1")
+        );
+        assert_eq!(
+            common_name(&certificate("server.pem")).as_deref(),
+            Some("localhost")
+        );
+    }
+
+    #[test]
+    fn rejects_truncated_certificates() {
+        let certificate = certificate("client.pem");
+        for length in [0, 1, 10, certificate.len() / 2] {
+            assert_eq!(common_name(&certificate[..length]), None);
+        }
+    }
+}
@@ -0,0 +1,107 @@
+//! The byte stream native WebSocket connections run over
+
+use std::{
+    io,
+    net::SocketAddr,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use async_std::net::TcpStream;
+use futures::{AsyncRead, AsyncWrite};
+#[cfg(feature = "tls")]
+use futures_rustls::rustls::pki_types::CertificateDer;
+
+/// A TCP stream that may be wrapped in TLS
+///
+/// This is the same type with or without the `tls` feature, which only adds the
+/// [`MaybeTlsStream::Tls`] variant.
+#[non_exhaustive]
+pub enum MaybeTlsStream {
+    /// A clear text stream
+    Plain(TcpStream),
+    /// A stream accepted with the server's TLS configuration
+    #[cfg(feature = "tls")]
+    Tls(Box<futures_rustls::server::TlsStream<TcpStream>>),
+}
+
+impl MaybeTlsStream {
+    /// The address of the peer at the other end of the stream
+    pub fn peer_addr(&self) -> io::Result<SocketAddr> {
+        self.tcp_stream().peer_addr()
+    }
+
+    /// The certificate chain the client presented, which the server verified against its
+    /// client roots
+    ///
+    /// `None` for clear text streams and for servers that do not ask for client certificates,
+    /// see [`crate::tls::load_server_config_with_client_auth`].
+    #[cfg(feature = "tls")]
+    pub fn peer_certificates(&self) -> Option<&[CertificateDer<'static>]> {
+        match self {
+            Self::Plain(_) => None,
+            Self::Tls(stream) => stream.get_ref().1.peer_certificates(),
+        }
+    }
+
+    /// The common name in the subject of the client's verified certificate, such as a player
+    /// or device ID
+    ///
+    /// A `handshake_fn` can read this with `stream.get_ref().peer_common_name()`.
+    #[cfg(feature = "tls")]
+    pub fn peer_common_name(&self) -> Option<String> {
+        crate::tls::common_name(self.peer_certificates()?.first()?)
+    }
+
+    fn tcp_stream(&self) -> &TcpStream {
+        match self {
+            Self::Plain(stream) => stream,
+            #[cfg(feature = "tls")]
+            Self::Tls(stream) => stream.get_ref().0,
+        }
+    }
+}
+
+impl AsyncRead for MaybeTlsStream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        match self.get_mut() {
+            Self::Plain(stream) => Pin::new(stream).poll_read(cx, buf),
+            #[cfg(feature = "tls")]
+            Self::Tls(stream) => Pin::new(stream).poll_read(cx, buf),
+        }
+    }
+}
+
+impl AsyncWrite for MaybeTlsStream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        match self.get_mut() {
+            Self::Plain(stream) => Pin::new(stream).poll_write(cx, buf),
+            #[cfg(feature = "tls")]
+            Self::Tls(stream) => Pin::new(stream).poll_write(cx, buf),
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Self::Plain(stream) => Pin::new(stream).poll_flush(cx),
+            #[cfg(feature = "tls")]
+            Self::Tls(stream) => Pin::new(stream).poll_flush(cx),
+        }
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Self::Plain(stream) => Pin::new(stream).poll_close(cx),
+            #[cfg(feature = "tls")]
+            Self::Tls(stream) => Pin::new(stream).poll_close(cx),
+        }
+    }
+}
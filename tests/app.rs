@@ -0,0 +1,192 @@
+//! The websocket plugins running inside a bevy `App`
+
+#![cfg(not(target_arch = "wasm32"))]
+
+use std::{
+    net::SocketAddr,
+    time::{Duration, Instant},
+};
+
+use async_std::{future::timeout, stream::StreamExt, task};
+use async_tungstenite::tungstenite::Message;
+use bevy::{app::AppExit, prelude::*, tasks::TaskPool};
+use bevy_eventwork::{managers::NetworkProvider, EventworkRuntime, Network, NetworkMessage};
+use bevy_eventwork_mod_websockets::{
+    BoundAddress, ConnectedPeerCount, NetworkSettings, ShutdownTimeout, WebSocketProvider,
+    WebSocketServerPlugin,
+};
+use serde::{Deserialize, Serialize};
+
+type Socket = <WebSocketProvider as NetworkProvider>::Socket;
+
+/// How long a test waits for something that should happen right away
+const PATIENCE: Duration = Duration::from_secs(5);
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+struct Chat(String);
+
+impl NetworkMessage for Chat {
+    const NAME: &'static str = "app:Chat";
+}
+
+fn server_app(settings: NetworkSettings) -> App {
+    let mut app = App::new();
+    app.add_plugins(WebSocketServerPlugin { settings });
+    app.update();
+    app
+}
+
+/// Updates `app` until `done` returns true, panicking if that takes longer than [`PATIENCE`]
+fn update_until(app: &mut App, done: impl Fn(&App) -> bool) {
+    let start = Instant::now();
+    while !done(app) {
+        assert!(start.elapsed() < PATIENCE, "Timed out updating the app");
+        app.update();
+        std::thread::sleep(Duration::from_millis(5));
+    }
+}
+
+/// Listens on a free localhost port, returning the address the server is bound to
+fn listen(app: &mut App) -> SocketAddr {
+    app.world_mut()
+        .resource_scope(|world, mut network: Mut<Network<WebSocketProvider>>| {
+            let runtime = world.resource::<EventworkRuntime<TaskPool>>();
+            let settings = world.resource::<NetworkSettings>();
+            network
+                .listen(SocketAddr::from(([127, 0, 0, 1], 0)), &runtime.0, settings)
+                .unwrap();
+        });
+    update_until(app, |app| app.world().contains_resource::<BoundAddress>());
+    **app.world().resource::<BoundAddress>()
+}
+
+async fn connect(addr: SocketAddr) -> Socket {
+    let url = url::Url::parse(&format!("ws://{}", addr)).unwrap();
+    timeout(
+        PATIENCE,
+        WebSocketProvider::connect_task(url, NetworkSettings::default()),
+    )
+    .await
+    .unwrap()
+    .unwrap()
+}
+
+fn decode(message: Message) -> Chat {
+    let Message::Binary(data) = message else {
+        panic!("Expected a binary packet, got {:?}", message);
+    };
+    let (kind, data): (String, Vec<u8>) = bincode::deserialize(&data).unwrap();
+    assert_eq!(kind, Chat::NAME);
+    bincode::deserialize(&data).unwrap()
+}
+
+#[test]
+fn exiting_delivers_queued_messages_then_closes() {
+    let shutdown_timeout = Duration::from_secs(2);
+    let mut settings = NetworkSettings::default();
+    settings.shutdown_timeout = ShutdownTimeout(shutdown_timeout);
+    let mut app = server_app(settings);
+    let addr = listen(&mut app);
+
+    let mut client = task::block_on(connect(addr));
+    update_until(&mut app, |app| {
+        app.world()
+            .resource::<Network<WebSocketProvider>>()
+            .has_connections()
+    });
+
+    let network = app.world().resource::<Network<WebSocketProvider>>();
+    for index in 0..3 {
+        network.broadcast(Chat(format!("goodbye {}", index)));
+    }
+    app.world_mut().send_event(AppExit::Success);
+    app.update();
+
+    task::block_on(async {
+        let received = timeout(shutdown_timeout, async {
+            let mut received = Vec::new();
+            while let Some(message) = client.next().await {
+                received.push(message.unwrap());
+            }
+            received
+        })
+        .await
+        .expect("The server did not close the connection within the shutdown timeout");
+
+        let (close, packets) = received.split_last().unwrap();
+        assert!(matches!(close, Message::Close(_)), "{:?}", close);
+        let packets: Vec<_> = packets.iter().cloned().map(decode).collect();
+        assert_eq!(
+            packets,
+            (0..3)
+                .map(|index| Chat(format!("goodbye {}", index)))
+                .collect::<Vec<_>>()
+        );
+
+        // The listener is dropped once the accept loop sees the shutdown
+        timeout(PATIENCE, async {
+            while async_std::net::TcpStream::connect(addr).await.is_ok() {
+                task::sleep(Duration::from_millis(5)).await;
+            }
+        })
+        .await
+        .expect("The server kept accepting connections after exiting");
+    });
+}
+
+#[test]
+fn apps_sharing_settings_keep_their_own_connections() {
+    let settings = NetworkSettings::default();
+    let mut first = server_app(settings.clone());
+    let mut second = server_app(settings);
+    let first_addr = listen(&mut first);
+    let second_addr = listen(&mut second);
+    assert_ne!(first_addr, second_addr);
+
+    let _client = task::block_on(connect(first_addr));
+    update_until(&mut first, |app| {
+        **app.world().resource::<ConnectedPeerCount>() == 1
+    });
+    second.update();
+    assert_eq!(**second.world().resource::<ConnectedPeerCount>(), 0);
+
+    first.world_mut().send_event(AppExit::Success);
+    first.update();
+
+    let _client = task::block_on(connect(second_addr));
+    update_until(&mut second, |app| {
+        **app.world().resource::<ConnectedPeerCount>() == 1
+    });
+}
+
+fn connected_peers(app: &App) -> usize {
+    **app.world().resource::<ConnectedPeerCount>()
+}
+
+#[test]
+fn waits_for_a_free_slot_past_max_connections() {
+    let mut settings = NetworkSettings::default();
+    settings.max_connections = Some(2);
+    let mut app = server_app(settings);
+    let addr = listen(&mut app);
+
+    let mut clients = Vec::new();
+    for connected in 1..=2 {
+        clients.push(task::block_on(connect(addr)));
+        update_until(&mut app, |app| connected_peers(app) == connected);
+    }
+
+    let mut waiting = task::spawn(connect(addr));
+    task::block_on(async {
+        assert!(timeout(Duration::from_millis(300), &mut waiting)
+            .await
+            .is_err());
+    });
+    app.update();
+    assert_eq!(connected_peers(&app), 2);
+
+    drop(clients.remove(0));
+    update_until(&mut app, |app| connected_peers(app) == 1);
+    let _last = task::block_on(waiting);
+    update_until(&mut app, |app| connected_peers(app) == 2);
+}
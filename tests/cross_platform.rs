@@ -0,0 +1,123 @@
+//! A native server talking to a client that frames packets like the WASM provider
+//!
+//! The WASM provider cannot run outside a browser, so the client is a raw tungstenite client
+//! sending every packet as a bincode encoded `NetworkPacket` in a binary message, as
+//! `WasmWebSocketProvider::send_loop` does.
+
+#![cfg(not(target_arch = "wasm32"))]
+
+use std::{
+    net::SocketAddr,
+    time::{Duration, Instant},
+};
+
+use async_std::{future::timeout, stream::StreamExt, task};
+use async_tungstenite::tungstenite::Message;
+use bevy::{prelude::*, tasks::TaskPool};
+use bevy_eventwork::{
+    AppNetworkMessage, ConnectionId, EventworkRuntime, Network, NetworkData, NetworkMessage,
+};
+use bevy_eventwork_mod_websockets::{
+    BoundAddress, NetworkSettings, WebSocketProvider, WebSocketServerPlugin,
+};
+use serde::{Deserialize, Serialize};
+
+/// How long a test waits for something that should happen right away
+const PATIENCE: Duration = Duration::from_secs(5);
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+struct Chat {
+    from: String,
+    text: String,
+    emotes: Vec<u32>,
+}
+
+impl NetworkMessage for Chat {
+    const NAME: &'static str = "cross_platform:Chat";
+}
+
+#[derive(Resource, Default)]
+struct ReceivedChats(Vec<(ConnectionId, Chat)>);
+
+fn receive_chats(mut chats: EventReader<NetworkData<Chat>>, mut received: ResMut<ReceivedChats>) {
+    for chat in chats.read() {
+        received.0.push((*chat.source(), (**chat).clone()));
+    }
+}
+
+/// Updates `app` until `done` returns true, panicking if that takes longer than [`PATIENCE`]
+fn update_until(app: &mut App, done: impl Fn(&App) -> bool) {
+    let start = Instant::now();
+    while !done(app) {
+        assert!(start.elapsed() < PATIENCE, "Timed out updating the app");
+        app.update();
+        std::thread::sleep(Duration::from_millis(5));
+    }
+}
+
+/// A server app listening for [`Chat`] on a free localhost port
+fn server() -> (App, SocketAddr) {
+    let mut app = App::new();
+    app.add_plugins(WebSocketServerPlugin::default())
+        .listen_for_message::<Chat, WebSocketProvider>()
+        .init_resource::<ReceivedChats>()
+        .add_systems(Update, receive_chats);
+    app.update();
+
+    app.world_mut()
+        .resource_scope(|world, mut network: Mut<Network<WebSocketProvider>>| {
+            let runtime = world.resource::<EventworkRuntime<TaskPool>>();
+            let settings = world.resource::<NetworkSettings>();
+            network
+                .listen(SocketAddr::from(([127, 0, 0, 1], 0)), &runtime.0, settings)
+                .unwrap();
+        });
+    update_until(&mut app, |app| {
+        app.world().contains_resource::<BoundAddress>()
+    });
+    let addr = **app.world().resource::<BoundAddress>();
+    (app, addr)
+}
+
+/// Encodes `message` the way the WASM provider sends it
+fn wasm_framed<T: NetworkMessage>(message: &T) -> Message {
+    let packet = (T::NAME, bincode::serialize(message).unwrap());
+    Message::Binary(bincode::serialize(&packet).unwrap())
+}
+
+fn chat(text: &str) -> Chat {
+    Chat {
+        from: String::from("browser"),
+        text: String::from(text),
+        emotes: vec![1, 2, 3],
+    }
+}
+
+#[test]
+fn exchanges_messages_with_wasm_framed_clients() {
+    let (mut app, addr) = server();
+    let (mut client, _) = task::block_on(async_tungstenite::async_std::connect_async(format!(
+        "ws://{}",
+        addr
+    )))
+    .unwrap();
+
+    task::block_on(client.send(wasm_framed(&chat("hello from wasm")))).unwrap();
+    update_until(&mut app, |app| {
+        !app.world().resource::<ReceivedChats>().0.is_empty()
+    });
+    let (source, received) = app.world().resource::<ReceivedChats>().0[0].clone();
+    assert_eq!(received, chat("hello from wasm"));
+
+    // The WASM recv loop decodes the same framing the native send loop produces
+    app.world()
+        .resource::<Network<WebSocketProvider>>()
+        .send_message(source, chat("hello from native"))
+        .unwrap();
+    app.update();
+    let reply = task::block_on(timeout(PATIENCE, client.next()))
+        .unwrap()
+        .unwrap()
+        .unwrap();
+    assert_eq!(reply, wasm_framed(&chat("hello from native")));
+}
@@ -0,0 +1,28 @@
+#!/bin/sh
+# Regenerates the certificates used by tests/tls.rs. They are valid for 100 years.
+#
+# ca.pem signs server.pem for localhost and 127.0.0.1, and client.pem for `player-1`.
+# intruder.pem is signed by another CA, so servers trusting ca.pem reject it.
+set -e
+cd "$(dirname "$0")"
+
+for name in ca server client other_ca intruder; do
+    openssl genpkey -algorithm EC -pkeyopt ec_paramgen_curve:P-256 -out "$name.key"
+done
+
+openssl req -x509 -new -key ca.key -subj "/CN=Test CA" -days 36500 \
+    -config ext.cnf -extensions ca -out ca.pem
+openssl req -x509 -new -key other_ca.key -subj "/CN=Other CA" -days 36500 \
+    -config ext.cnf -extensions ca -out other_ca.pem
+
+sign() {
+    openssl req -new -key "$1.key" -subj "/CN=$3" -config ext.cnf -out "$1.csr"
+    openssl x509 -req -in "$1.csr" -CA "$2.pem" -CAkey "$2.key" -CAcreateserial \
+        -days 36500 -extfile ext.cnf -extensions "$4" -out "$1.pem"
+    rm "$1.csr"
+}
+sign server ca localhost server
+sign client ca player-1 client
+sign intruder other_ca intruder client
+
+rm -f ca.key other_ca.key other_ca.pem ./*.srl
@@ -0,0 +1,177 @@
+//! `wss://` connections to native servers, using the certificates in `tests/fixtures/tls`
+
+#![cfg(all(feature = "tls", not(target_arch = "wasm32")))]
+
+use std::{
+    fs::File,
+    io::BufReader,
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+
+use async_std::{future::timeout, net::TcpStream, stream::StreamExt, task};
+use async_tungstenite::{tungstenite::Message, WebSocketStream};
+use bevy_eventwork::{managers::NetworkProvider, NetworkPacket};
+use bevy_eventwork_mod_websockets::{
+    tls::{load_server_config, load_server_config_with_client_auth},
+    NetworkSettings, WebSocketProvider,
+};
+use futures_rustls::{
+    client::TlsStream,
+    rustls::{
+        pki_types::{CertificateDer, PrivateKeyDer, ServerName},
+        ClientConfig, RootCertStore, ServerConfig,
+    },
+    TlsConnector,
+};
+
+type Socket = <WebSocketProvider as NetworkProvider>::Socket;
+
+/// How long a test waits for something that should happen right away
+const PATIENCE: Duration = Duration::from_secs(5);
+
+fn fixture(name: &str) -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/fixtures/tls")
+        .join(name)
+}
+
+fn certificates(name: &str) -> Vec<CertificateDer<'static>> {
+    let mut pem = BufReader::new(File::open(fixture(name)).unwrap());
+    rustls_pemfile::certs(&mut pem)
+        .collect::<Result<_, _>>()
+        .unwrap()
+}
+
+fn private_key(name: &str) -> PrivateKeyDer<'static> {
+    let mut pem = BufReader::new(File::open(fixture(name)).unwrap());
+    rustls_pemfile::private_key(&mut pem).unwrap().unwrap()
+}
+
+/// The roots trusting the test CA, which signed the server and `player-1` certificates
+fn test_roots() -> RootCertStore {
+    let mut roots = RootCertStore::empty();
+    for certificate in certificates("ca.pem") {
+        roots.add(certificate).unwrap();
+    }
+    roots
+}
+
+fn server_config() -> Arc<ServerConfig> {
+    load_server_config(fixture("server.pem"), fixture("server.key")).unwrap()
+}
+
+fn server_config_with_client_auth() -> Arc<ServerConfig> {
+    load_server_config_with_client_auth(fixture("server.pem"), fixture("server.key"), test_roots())
+        .unwrap()
+}
+
+/// Listens with `tls_config`, returning the accepted connections and the server's address
+async fn listen(tls_config: Arc<ServerConfig>) -> (async_channel::Receiver<Socket>, SocketAddr) {
+    let mut settings = NetworkSettings::default();
+    settings.tls_config = Some(tls_config);
+    let mut incoming =
+        WebSocketProvider::accept_loop(SocketAddr::from(([127, 0, 0, 1], 0)), settings.clone())
+            .await
+            .unwrap();
+
+    // The server's handshakes only progress while the accept stream is polled
+    let (accepted, accepted_receiver) = async_channel::unbounded();
+    task::spawn(async move {
+        while let Some(socket) = incoming.next().await {
+            let _ = accepted.send(socket).await;
+        }
+    });
+    (accepted_receiver, settings.bound_addr().unwrap())
+}
+
+/// Connects to `wss://localhost`, presenting the certificate and key named by `identity`
+async fn connect(
+    addr: SocketAddr,
+    identity: Option<(&str, &str)>,
+) -> Result<WebSocketStream<TlsStream<TcpStream>>, String> {
+    let config = ClientConfig::builder().with_root_certificates(test_roots());
+    let config = match identity {
+        Some((certificate, key)) => config
+            .with_client_auth_cert(certificates(certificate), private_key(key))
+            .unwrap(),
+        None => config.with_no_client_auth(),
+    };
+
+    let stream = TcpStream::connect(addr).await.unwrap();
+    let stream = TlsConnector::from(Arc::new(config))
+        .connect(ServerName::try_from("localhost").unwrap(), stream)
+        .await
+        .map_err(|err| err.to_string())?;
+    let (socket, _) = async_tungstenite::client_async("wss://localhost/", stream)
+        .await
+        .map_err(|err| err.to_string())?;
+    Ok(socket)
+}
+
+#[test]
+fn exchanges_packets_over_wss() {
+    task::block_on(async {
+        let (accepted, addr) = listen(server_config()).await;
+        let mut client = timeout(PATIENCE, connect(addr, None))
+            .await
+            .unwrap()
+            .unwrap();
+        let server = timeout(PATIENCE, accepted.recv()).await.unwrap().unwrap();
+        assert_eq!(server.get_ref().peer_common_name(), None);
+
+        let (read_half, _write_half) = WebSocketProvider::split(server);
+        let (received_sender, received) = async_channel::unbounded();
+        task::spawn(WebSocketProvider::recv_loop(
+            read_half,
+            received_sender,
+            NetworkSettings::default(),
+        ));
+
+        let encoded = bincode::serialize(&("chat", [1u8, 2, 3].as_slice())).unwrap();
+        client.send(Message::Binary(encoded.clone())).await.unwrap();
+        let received: NetworkPacket = timeout(PATIENCE, received.recv()).await.unwrap().unwrap();
+        assert_eq!(bincode::serialize(&received).unwrap(), encoded);
+    });
+}
+
+#[test]
+fn accepts_clients_with_a_trusted_certificate() {
+    task::block_on(async {
+        let (accepted, addr) = listen(server_config_with_client_auth()).await;
+        let _client = timeout(PATIENCE, connect(addr, Some(("client.pem", "client.key"))))
+            .await
+            .unwrap()
+            .unwrap();
+
+        let server = timeout(PATIENCE, accepted.recv()).await.unwrap().unwrap();
+        assert_eq!(
+            server.get_ref().peer_common_name().as_deref(),
+            Some("player-1")
+        );
+    });
+}
+
+/// Connects with `identity` to a server requiring client certificates, asserting it is rejected
+async fn assert_rejected(identity: Option<(&str, &str)>) {
+    let (accepted, addr) = listen(server_config_with_client_auth()).await;
+
+    // With TLS 1.3 the client only learns of the rejection once it reads
+    let connected = timeout(PATIENCE, connect(addr, identity)).await.unwrap();
+    assert!(connected.is_err());
+    assert!(timeout(Duration::from_millis(200), accepted.recv())
+        .await
+        .is_err());
+}
+
+#[test]
+fn rejects_clients_without_a_certificate() {
+    task::block_on(assert_rejected(None));
+}
+
+#[test]
+fn rejects_clients_with_an_untrusted_certificate() {
+    task::block_on(assert_rejected(Some(("intruder.pem", "intruder.key"))));
+}